@@ -0,0 +1,124 @@
+// src/checkpoint.rs
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+/// How far an import got: every row up to and including `row` has been
+/// written or rejected, and the next row starts at byte `offset` of the input.
+/// With several inputs, the ones already imported in full are listed in
+/// `completed`, and `input` is the one in progress.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResumeCheckpoint {
+    pub input: String,
+    pub sha256: String,
+    pub row: usize,
+    pub line: u64,
+    pub offset: u64,
+    #[serde(default)]
+    pub completed: Vec<CompletedInput>,
+}
+
+/// An input whose every row was written or rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletedInput {
+    pub input: String,
+    pub sha256: String,
+}
+
+impl ResumeCheckpoint {
+    /// Reads a checkpoint file, or returns `None` when there is none yet.
+    pub fn load(path: &str) -> Result<Option<ResumeCheckpoint>> {
+        if !Path::new(path).exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(path)?;
+        let checkpoint = serde_json::from_str(&data)
+            .map_err(|e| anyhow!("Invalid checkpoint file {}: {}", path, e))?;
+        Ok(Some(checkpoint))
+    }
+
+    /// Writes the checkpoint next to its final path and renames it into
+    /// place, so a crash never leaves a half-written checkpoint behind.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Hex SHA-256 of a file's contents.
+pub fn file_sha256(path: &str) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Where a row ends in the input, i.e. where the row after it starts.
+#[derive(Debug, Clone, Copy)]
+pub struct RowEnd {
+    pub line: u64,
+    pub offset: u64,
+}
+
+/// Moves the checkpoint forward as rows are settled. Rows settle out of
+/// order when several batches are in flight, so the checkpoint only ever
+/// covers the longest run of settled rows from the start.
+pub struct CheckpointTracker {
+    path: String,
+    checkpoint: ResumeCheckpoint,
+    settled: BTreeMap<usize, RowEnd>,
+    dirty: bool,
+}
+
+impl CheckpointTracker {
+    pub fn new(path: &str, checkpoint: ResumeCheckpoint) -> Self {
+        CheckpointTracker {
+            path: path.to_string(),
+            checkpoint,
+            settled: BTreeMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// Marks a row as written or rejected.
+    pub fn settle(&mut self, row_num: usize, end: RowEnd) {
+        self.settled.insert(row_num, end);
+        while let Some(end) = self.settled.remove(&(self.checkpoint.row + 1)) {
+            self.checkpoint.row += 1;
+            self.checkpoint.line = end.line;
+            self.checkpoint.offset = end.offset;
+            self.dirty = true;
+        }
+    }
+
+    /// Writes the checkpoint file if it moved since the last save.
+    pub fn save(&mut self) -> Result<()> {
+        if self.dirty {
+            self.checkpoint.save(&self.path)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Marks the input as fully imported and saves the checkpoint. Returns
+    /// the checkpoint to carry over to the next input.
+    pub fn complete(mut self) -> Result<ResumeCheckpoint> {
+        let checkpoint = &mut self.checkpoint;
+        checkpoint.completed.push(CompletedInput {
+            input: std::mem::take(&mut checkpoint.input),
+            sha256: std::mem::take(&mut checkpoint.sha256),
+        });
+        (checkpoint.row, checkpoint.line, checkpoint.offset) = (0, 0, 0);
+        self.checkpoint.save(&self.path)?;
+        Ok(self.checkpoint)
+    }
+}
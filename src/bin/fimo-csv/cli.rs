@@ -1,27 +1,62 @@
 // src/cli.rs
-use clap::Parser;
+use crate::dialect::CsvDialect;
+use crate::input::InputFormat;
+use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Input file, or - for stdin; gzip, zstd, bzip2 and xz input is decompressed.
+    /// Repeat it or give a glob such as 'data/*.csv' to import several files in order
+    #[arg(long, required = true)]
+    pub input: Vec<String>,
+
+    /// Input format (default: from the file extension, otherwise csv)
+    #[arg(long, value_enum)]
+    pub format: Option<InputFormat>,
+
+    /// Spreadsheet to read from a workbook, by name or position starting at 1 (default: the first)
     #[arg(long)]
-    pub input: String,
+    pub sheet: Option<String>,
 
+    /// Cells to read from the sheet, e.g. B3:H200, or only the top-left cell (default: the used area)
     #[arg(long)]
-    pub mapping: String,
+    pub cell_range: Option<String>,
 
+    /// Rows to skip above the header row of a sheet (default: 0)
     #[arg(long)]
+    pub skip_rows: Option<u32>,
+
+    // The connection and mapping options are required for imports only. The
+    // empty defaults are never used; they let clap build `Cli` for `infer`.
+    #[arg(long, required = true, default_value = "", hide_default_value = true)]
+    pub mapping: String,
+
+    #[arg(long, required = true, default_value = "", hide_default_value = true)]
     pub mongo_uri: String,
 
-    #[arg(long)]
+    #[arg(long, required = true, default_value = "", hide_default_value = true)]
     pub db: String,
 
-    #[arg(long)]
+    #[arg(long, required = true, default_value = "", hide_default_value = true)]
     pub collection: String,
 
     #[arg(long)]
     pub operation: Option<String>,
 
+    /// Take each row's operation from this input column, e.g. op
+    #[arg(long, conflicts_with_all = ["operation", "op_expr"])]
+    pub op_column: Option<String>,
+
+    /// Render each row's operation with a MiniJinja expression, e.g.
+    /// "{{ 'delete' if row.deleted else 'upsert' }}"
+    #[arg(long, conflicts_with = "operation")]
+    pub op_expr: Option<String>,
+
     #[arg(long)]
     pub template_dir: Option<String>,
 
@@ -45,4 +80,115 @@ pub struct Cli {
 
     #[arg(long)]
     pub extended_json: bool,
+
+    /// Parallel update_one calls per unordered batch before MongoDB 8.0 or without --batch-size (default: 10)
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+
+    /// Threads that map and render rows (default: number of CPUs)
+    #[arg(long)]
+    pub workers: Option<usize>,
+
+    /// Batches written concurrently with --unordered (default: 4)
+    #[arg(long)]
+    pub max_inflight_batches: Option<usize>,
+
+    /// Write rows in any order, with several batches in flight and unordered bulk writes
+    #[arg(long)]
+    pub unordered: bool,
+
+    /// Record the last fully written row and its byte offset in this file after every batch
+    #[arg(long)]
+    pub checkpoint_file: Option<String>,
+
+    /// Continue after the row recorded in --checkpoint-file; refused if the input file changed
+    #[arg(long, requires = "checkpoint_file")]
+    pub resume: bool,
+
+    /// Write rows that fail to this CSV file, unchanged, with row number, stage and error columns
+    #[arg(long)]
+    pub reject_file: Option<String>,
+
+    /// Write the import summary as JSON to this file
+    #[arg(long)]
+    pub report: Option<String>,
+
+    /// Add the source file, row number and run id to every document under this field
+    #[arg(long)]
+    pub provenance: Option<String>,
+
+    /// Id of this import run, for provenance and the report; a new ObjectId by default
+    #[arg(long)]
+    pub run_id: Option<String>,
+
+    /// Abort the import once more than this many rows have failed
+    #[arg(long)]
+    pub max_errors: Option<usize>,
+
+    /// Treat mapping warnings (unmapped columns, fields missing from the header) as errors
+    #[arg(long)]
+    pub strict: bool,
+
+    #[command(flatten)]
+    pub dialect: CsvDialect,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Guess a mapping file from a sample of a CSV file
+    Infer(InferArgs),
+    /// Print the $jsonSchema validator of a mapping file, and optionally apply it with its indexes
+    Schema(SchemaArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct InferArgs {
+    /// CSV file to sample, or - for stdin; compressed input is decompressed
+    #[arg(long)]
+    pub input: String,
+
+    /// Rows to sample from the start of the file (default: 1000)
+    #[arg(long)]
+    pub sample_rows: Option<usize>,
+
+    /// Write the mapping to this file instead of stdout
+    #[arg(long)]
+    pub output: Option<String>,
+
+    #[arg(long)]
+    pub no_header: bool,
+
+    #[command(flatten)]
+    pub dialect: CsvDialect,
+}
+
+#[derive(Args, Debug)]
+pub struct SchemaArgs {
+    #[arg(long)]
+    pub mapping: String,
+
+    /// Write the validator to this file instead of stdout
+    #[arg(long)]
+    pub output: Option<String>,
+
+    /// Set the validator on the collection (collMod, or create it) and create the mapping's indexes
+    #[arg(long, requires_all = ["mongo_uri", "db", "collection"])]
+    pub apply: bool,
+
+    #[arg(long)]
+    pub mongo_uri: Option<String>,
+
+    #[arg(long)]
+    pub db: Option<String>,
+
+    #[arg(long)]
+    pub collection: Option<String>,
+
+    /// Which documents the validator checks: strict, moderate or off (default: strict)
+    #[arg(long, value_parser = ["strict", "moderate", "off"])]
+    pub validation_level: Option<String>,
+
+    /// What happens to invalid documents: error or warn (default: error)
+    #[arg(long, value_parser = ["error", "warn"])]
+    pub validation_action: Option<String>,
 }
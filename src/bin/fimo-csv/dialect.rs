@@ -0,0 +1,282 @@
+// src/dialect.rs
+use crate::encoding::parse_encoding;
+use anyhow::{anyhow, Result};
+use clap::Args;
+use encoding_rs::Encoding;
+use csv::{ReaderBuilder, Terminator, Trim, WriterBuilder};
+use serde::Deserialize;
+
+/// Number of bytes inspected when sniffing the dialect of an input file.
+pub const SNIFF_BYTES: usize = 64 * 1024;
+
+const SNIFF_DELIMITERS: [u8; 5] = [b',', b';', b'\t', b'|', b':'];
+const SNIFF_LINES: usize = 50;
+
+/// CSV dialect settings. Every option can be set from the command line or
+/// from the `csv:` section of the mapping file; the command line wins.
+#[derive(Args, Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvDialect {
+    /// Field delimiter, e.g. `,`, `;`, `|` or `\t` (default: `,`)
+    #[arg(long)]
+    pub delimiter: Option<String>,
+
+    /// Quote character (default: `"`)
+    #[arg(long)]
+    pub quote: Option<String>,
+
+    /// Escape character used inside quoted fields, e.g. `\` (default: none)
+    #[arg(long)]
+    pub escape: Option<String>,
+
+    /// Treat two consecutive quotes inside a quoted field as one (default: true)
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub double_quote: Option<bool>,
+
+    /// Skip lines starting with this character, e.g. `#`
+    #[arg(long)]
+    pub comment: Option<String>,
+
+    /// Record terminator: `crlf` (any of \r, \n, \r\n) or a single character (default: crlf)
+    #[arg(long)]
+    pub terminator: Option<String>,
+
+    /// Allow records with a varying number of fields (default: false)
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub flexible: Option<bool>,
+
+    /// Trim whitespace: none, headers, fields or all (default: none)
+    #[arg(long)]
+    pub trim: Option<String>,
+
+    /// Auto-detect delimiter, quote and terminator from the start of the file
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub sniff: Option<bool>,
+
+    /// Character encoding, e.g. utf-8, utf-16le or windows-1252 (default: auto)
+    #[arg(long)]
+    pub encoding: Option<String>,
+}
+
+impl CsvDialect {
+    /// Returns a dialect where every option set in `other` replaces the one in `self`.
+    pub fn overridden_by(self, other: CsvDialect) -> CsvDialect {
+        CsvDialect {
+            delimiter: other.delimiter.or(self.delimiter),
+            quote: other.quote.or(self.quote),
+            escape: other.escape.or(self.escape),
+            double_quote: other.double_quote.or(self.double_quote),
+            comment: other.comment.or(self.comment),
+            terminator: other.terminator.or(self.terminator),
+            flexible: other.flexible.or(self.flexible),
+            trim: other.trim.or(self.trim),
+            sniff: other.sniff.or(self.sniff),
+            encoding: other.encoding.or(self.encoding),
+        }
+    }
+
+    pub fn sniff_enabled(&self) -> bool {
+        self.sniff.unwrap_or(false)
+    }
+
+    /// The encoding to decode the input with, or `None` to detect it.
+    pub fn encoding(&self) -> Result<Option<&'static Encoding>> {
+        match &self.encoding {
+            Some(label) => parse_encoding(label),
+            None => Ok(None),
+        }
+    }
+
+    /// Validates the dialect and turns it into a configured `csv::ReaderBuilder`.
+    pub fn reader_builder(&self) -> Result<ReaderBuilder> {
+        let mut builder = ReaderBuilder::new();
+
+        if let Some(delimiter) = &self.delimiter {
+            builder.delimiter(parse_byte("delimiter", delimiter)?);
+        }
+        if let Some(quote) = &self.quote {
+            builder.quote(parse_byte("quote", quote)?);
+        }
+        if let Some(escape) = &self.escape {
+            builder.escape(Some(parse_byte("escape", escape)?));
+        }
+        if let Some(double_quote) = self.double_quote {
+            builder.double_quote(double_quote);
+        }
+        if let Some(comment) = &self.comment {
+            builder.comment(Some(parse_byte("comment", comment)?));
+        }
+        if let Some(terminator) = &self.terminator {
+            builder.terminator(parse_terminator(terminator)?);
+        }
+        if let Some(flexible) = self.flexible {
+            builder.flexible(flexible);
+        }
+        if let Some(trim) = &self.trim {
+            builder.trim(parse_trim(trim)?);
+        }
+
+        Ok(builder)
+    }
+
+    /// A `csv::WriterBuilder` producing files this dialect can read back.
+    pub fn writer_builder(&self) -> Result<WriterBuilder> {
+        let mut builder = WriterBuilder::new();
+
+        if let Some(delimiter) = &self.delimiter {
+            builder.delimiter(parse_byte("delimiter", delimiter)?);
+        }
+        if let Some(quote) = &self.quote {
+            builder.quote(parse_byte("quote", quote)?);
+        }
+        if let Some(escape) = &self.escape {
+            builder.escape(parse_byte("escape", escape)?);
+        }
+        if let Some(double_quote) = self.double_quote {
+            builder.double_quote(double_quote);
+        }
+        if let Some(terminator) = &self.terminator {
+            builder.terminator(parse_terminator(terminator)?);
+        }
+
+        Ok(builder)
+    }
+}
+
+/// Parses a single-byte dialect option. Accepts a literal ASCII character,
+/// `tab`, the escapes `\t`, `\n`, `\r`, `\0`, `\\` and hex bytes like `\x1f`.
+fn parse_byte(option: &str, value: &str) -> Result<u8> {
+    let byte = match value {
+        "tab" | "\\t" => b'\t',
+        "\\n" => b'\n',
+        "\\r" => b'\r',
+        "\\0" => b'\0',
+        "\\\\" => b'\\',
+        v if v.starts_with("\\x") && v.len() == 4 => u8::from_str_radix(&v[2..], 16)
+            .map_err(|_| anyhow!("Invalid hex byte '{}' for CSV option '{}'", v, option))?,
+        v if v.len() == 1 && v.is_ascii() => v.as_bytes()[0],
+        v => {
+            return Err(anyhow!(
+                "CSV option '{}' must be a single ASCII character, got '{}'",
+                option,
+                v
+            ))
+        }
+    };
+    Ok(byte)
+}
+
+fn parse_terminator(value: &str) -> Result<Terminator> {
+    if value.eq_ignore_ascii_case("crlf") {
+        Ok(Terminator::CRLF)
+    } else {
+        parse_byte("terminator", value).map(Terminator::Any)
+    }
+}
+
+fn parse_trim(value: &str) -> Result<Trim> {
+    match value.to_lowercase().as_str() {
+        "none" => Ok(Trim::None),
+        "headers" => Ok(Trim::Headers),
+        "fields" => Ok(Trim::Fields),
+        "all" => Ok(Trim::All),
+        other => Err(anyhow!(
+            "Invalid trim mode '{}', expected none, headers, fields or all",
+            other
+        )),
+    }
+}
+
+/// Guesses delimiter, quote character and record terminator from a sample of
+/// the input. Options that cannot be detected are left unset.
+pub fn sniff(sample: &[u8]) -> CsvDialect {
+    let mut dialect = CsvDialect::default();
+
+    let has_lf = sample.contains(&b'\n');
+    if !has_lf && sample.contains(&b'\r') {
+        dialect.terminator = Some("\\r".to_string());
+    }
+    let line_end = if has_lf { b'\n' } else { b'\r' };
+
+    // The sample may end in the middle of a record, so drop the trailing partial line.
+    let complete = match sample.iter().rposition(|b| *b == line_end) {
+        Some(pos) => &sample[..pos],
+        None => sample,
+    };
+
+    let lines: Vec<&[u8]> = complete
+        .split(|b| *b == line_end)
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty())
+        .take(SNIFF_LINES)
+        .collect();
+
+    if lines.is_empty() {
+        return dialect;
+    }
+
+    let quote = sniff_quote(&lines);
+    dialect.quote = Some((quote as char).to_string());
+
+    let mut best: Option<(u8, usize, usize)> = None; // (delimiter, consistent lines, fields per line)
+    for delimiter in SNIFF_DELIMITERS {
+        let counts: Vec<usize> = lines
+            .iter()
+            .map(|line| count_unquoted(line, delimiter, quote))
+            .collect();
+        let first = counts[0];
+        if first == 0 {
+            continue;
+        }
+        let consistent = counts.iter().filter(|c| **c == first).count();
+        let better = match best {
+            None => true,
+            Some((_, best_consistent, best_fields)) => {
+                consistent > best_consistent || (consistent == best_consistent && first > best_fields)
+            }
+        };
+        if better {
+            best = Some((delimiter, consistent, first));
+        }
+    }
+
+    if let Some((delimiter, _, _)) = best {
+        dialect.delimiter = Some(match delimiter {
+            b'\t' => "\\t".to_string(),
+            other => (other as char).to_string(),
+        });
+    }
+
+    dialect
+}
+
+fn sniff_quote(lines: &[&[u8]]) -> u8 {
+    let starts_with = |quote: u8| {
+        lines
+            .iter()
+            .flat_map(|line| {
+                line.iter().enumerate().filter(move |(i, b)| {
+                    **b == quote && (*i == 0 || SNIFF_DELIMITERS.contains(&line[i - 1]))
+                })
+            })
+            .count()
+    };
+    if starts_with(b'\'') > starts_with(b'"') {
+        b'\''
+    } else {
+        b'"'
+    }
+}
+
+fn count_unquoted(line: &[u8], delimiter: u8, quote: u8) -> usize {
+    let mut in_quotes = false;
+    let mut count = 0;
+    for b in line {
+        if *b == quote {
+            in_quotes = !in_quotes;
+        } else if *b == delimiter && !in_quotes {
+            count += 1;
+        }
+    }
+    count
+}
@@ -0,0 +1,216 @@
+// src/encoding.rs
+use anyhow::{anyhow, Result};
+use encoding_rs::{Decoder, DecoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use std::io::{self, BufRead, Read};
+
+/// Bytes inspected when guessing the encoding of an input without a BOM.
+const DETECT_BYTES: usize = 4096;
+
+/// Written in place of a byte sequence that is invalid in the input's
+/// encoding. It is never valid UTF-8, so the record holding it is rejected
+/// at the parse stage instead of silently getting a replacement character.
+const INVALID_MARKER: u8 = 0xFF;
+
+/// Parses an `--encoding` value: `auto` (`None`) or a label such as `utf-8`,
+/// `utf-16le`, `windows-1252` or `latin1`.
+pub fn parse_encoding(label: &str) -> Result<Option<&'static Encoding>> {
+    if label.eq_ignore_ascii_case("auto") {
+        return Ok(None);
+    }
+    Encoding::for_label(label.trim().as_bytes())
+        .map(Some)
+        .ok_or_else(|| anyhow!("Unknown encoding '{}'", label))
+}
+
+/// The encoding of an input and the length of its BOM, from its first bytes.
+/// A BOM decides; otherwise NUL bytes in every other position mean UTF-16,
+/// valid UTF-8 means UTF-8, and anything else is taken as Windows-1252.
+pub fn detect(head: &[u8]) -> (&'static Encoding, usize) {
+    if let Some(found) = Encoding::for_bom(head) {
+        return found;
+    }
+    let head = &head[..head.len().min(DETECT_BYTES)];
+    let pairs = head.len() / 2;
+    let even_zeros = head.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd_zeros = head.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+    if pairs > 0 && odd_zeros > pairs / 2 && even_zeros < pairs / 8 {
+        return (UTF_16LE, 0);
+    }
+    if pairs > 0 && even_zeros > pairs / 2 && odd_zeros < pairs / 8 {
+        return (UTF_16BE, 0);
+    }
+    match std::str::from_utf8(head) {
+        // A multi-byte character cut off at the end of the sample is fine.
+        Err(e) if e.error_len().is_some() => (WINDOWS_1252, 0),
+        _ => (UTF_8, 0),
+    }
+}
+
+/// Length of the BOM at the start of `head` if it belongs to `encoding`.
+pub fn bom_length(head: &[u8], encoding: &'static Encoding) -> usize {
+    match Encoding::for_bom(head) {
+        Some((found, len)) if found == encoding => len,
+        _ => 0,
+    }
+}
+
+/// Transcodes a reader to UTF-8, dropping the BOM.
+pub struct Utf8Reader<R> {
+    inner: R,
+    decoder: Decoder,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    finished: bool,
+}
+
+impl<R: BufRead> Utf8Reader<R> {
+    pub fn new(inner: R, encoding: &'static Encoding, capacity: usize) -> Self {
+        Utf8Reader {
+            inner,
+            decoder: encoding.new_decoder_with_bom_removal(),
+            buf: vec![0; capacity.max(16)],
+            pos: 0,
+            len: 0,
+            finished: false,
+        }
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        self.pos = 0;
+        self.len = 0;
+        // One byte is kept free for the marker of a malformed sequence.
+        let room = self.buf.len() - 1;
+        while self.len == 0 && !self.finished {
+            let input = self.inner.fill_buf()?;
+            let last = input.is_empty();
+            let (result, read, written) =
+                self.decoder.decode_to_utf8_without_replacement(input, &mut self.buf[..room], last);
+            self.inner.consume(read);
+            self.len = written;
+            match result {
+                DecoderResult::InputEmpty => self.finished = last,
+                DecoderResult::OutputFull => {}
+                DecoderResult::Malformed(_, _) => {
+                    self.buf[self.len] = INVALID_MARKER;
+                    self.len += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for Utf8Reader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Utf8Reader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.len {
+            self.refill()?;
+        }
+        Ok(&self.buf[self.pos..self.len])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    /// Transcodes `bytes`, fed to the decoder `chunk` bytes at a time.
+    fn transcode(bytes: &[u8], encoding: &'static Encoding, chunk: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        Utf8Reader::new(BufReader::with_capacity(chunk, bytes), encoding, 16)
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn a_bom_decides_the_encoding() {
+        assert_eq!(detect(b"\xef\xbb\xbfid,name"), (UTF_8, 3));
+        assert_eq!(detect(b"\xff\xfei\x00d\x00"), (UTF_16LE, 2));
+        assert_eq!(detect(b"\xfe\xff\x00i\x00d"), (UTF_16BE, 2));
+        assert_eq!(bom_length(b"\xff\xfei\x00", UTF_16LE), 2);
+        assert_eq!(bom_length(b"\xff\xfei\x00", UTF_8), 0);
+    }
+
+    #[test]
+    fn nul_bytes_without_a_bom_mean_utf16() {
+        assert_eq!(detect(&utf16le("id,name\r\n1,Zoë\r\n")), (UTF_16LE, 0));
+        let be: Vec<u8> = "id,name\r\n".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(detect(&be), (UTF_16BE, 0));
+        // A few NULs in otherwise single-byte text are not enough.
+        assert_eq!(detect(b"id,name\x00,age\n1,a,2\n"), (UTF_8, 0));
+    }
+
+    #[test]
+    fn invalid_utf8_falls_back_to_windows_1252() {
+        assert_eq!(detect("id,name\n1,Zoë\n".as_bytes()), (UTF_8, 0));
+        assert_eq!(detect(b"id,name\n1,Zo\xeb\n"), (WINDOWS_1252, 0));
+        assert_eq!(detect(b""), (UTF_8, 0));
+        // A character cut off by the end of the sample is not an error.
+        let mut head = vec![b'a'; DETECT_BYTES - 1];
+        head.extend_from_slice("é".as_bytes());
+        assert_eq!(detect(&head), (UTF_8, 0));
+    }
+
+    #[test]
+    fn the_fixtures_are_detected() {
+        let utf16 = std::fs::read("tests/data/utf16.csv").unwrap();
+        assert_eq!(detect(&utf16), (UTF_16LE, 2));
+        let legacy = std::fs::read("tests/data/windows-1252.csv").unwrap();
+        assert_eq!(detect(&legacy), (WINDOWS_1252, 0));
+        let text = String::from_utf8(transcode(&legacy, WINDOWS_1252, 7)).unwrap();
+        assert!(text.contains("1,Zoë Müller,42"), "{}", text);
+    }
+
+    #[test]
+    fn characters_split_across_chunks_are_decoded_whole() {
+        let text = "id,name\r\n1,Zoë Müller\r\n2,Ærøskøbing €\r\n".repeat(3);
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend(utf16le(&text));
+        for chunk in [1, 3, 5, 64] {
+            assert_eq!(transcode(&bytes, UTF_16LE, chunk), text.as_bytes(), "chunk {}", chunk);
+        }
+        let (legacy, _, _) = WINDOWS_1252.encode(&text);
+        assert_eq!(transcode(&legacy, WINDOWS_1252, 1), text.as_bytes());
+    }
+
+    #[test]
+    fn invalid_sequences_become_a_marker_that_is_not_utf8() {
+        // An unpaired surrogate cannot be decoded.
+        let mut bytes = utf16le("a,");
+        bytes.extend_from_slice(&[0x00, 0xd8]);
+        bytes.extend(utf16le(",b\n"));
+        for chunk in [1, 64] {
+            let out = transcode(&bytes, UTF_16LE, chunk);
+            assert_eq!(out, b"a,\xff,b\n", "chunk {}", chunk);
+            assert!(std::str::from_utf8(&out).is_err());
+        }
+    }
+
+    #[test]
+    fn encoding_labels_are_parsed() {
+        assert_eq!(parse_encoding("AUTO").unwrap(), None);
+        assert_eq!(parse_encoding("latin1").unwrap(), Some(WINDOWS_1252));
+        assert_eq!(parse_encoding(" utf-16le ").unwrap(), Some(UTF_16LE));
+        assert!(parse_encoding("klingon").unwrap_err().to_string().contains("Unknown encoding 'klingon'"));
+    }
+}
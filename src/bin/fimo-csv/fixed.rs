@@ -0,0 +1,314 @@
+// src/fixed.rs
+use crate::checkpoint::RowEnd;
+use crate::input::{RawRecord, RecordSource};
+use anyhow::{anyhow, Result};
+use csv::ByteRecord;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io::BufRead;
+
+/// Layout of a fixed-width file, from the `fixed:` section of the mapping
+/// file. Columns either all give a `start` (with `length`), or all give a
+/// `width` and follow each other in order.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixedLayout {
+    /// Lines before the first record, such as a header record.
+    #[serde(default)]
+    pub skip_lines: usize,
+    pub columns: Vec<FixedColumn>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixedColumn {
+    pub name: String,
+    #[serde(default)]
+    pub start: Option<usize>, // First character of the column, counting from 1
+    #[serde(default)]
+    pub length: Option<usize>,
+    #[serde(default)]
+    pub width: Option<usize>, // Length of the column when columns are given in order
+    #[serde(default)]
+    pub trim: FixedTrim,
+    #[serde(default)]
+    pub pad: Option<String>, // Padding character removed by `trim`, defaults to a space
+    #[serde(default)]
+    pub skip: bool, // Filler that is not imported
+}
+
+/// Which side of a value its padding is removed from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FixedTrim {
+    #[default]
+    Both,
+    Left,
+    Right,
+    None,
+}
+
+/// A column resolved to character positions.
+struct Span {
+    start: usize,
+    len: usize,
+    trim: FixedTrim,
+    pad: char,
+}
+
+impl FixedLayout {
+    /// Names of the imported columns, in order.
+    pub fn names(&self) -> Vec<String> {
+        self.columns.iter().filter(|c| !c.skip).map(|c| c.name.clone()).collect()
+    }
+
+    fn spans(&self) -> Result<Vec<Span>> {
+        if self.columns.is_empty() {
+            return Err(anyhow!("The fixed section needs at least one column"));
+        }
+        let by_width = self.columns.iter().all(|c| c.width.is_some() && c.start.is_none());
+        let mut names = HashSet::new();
+        let mut next = 0;
+        let mut spans = Vec::new();
+        for column in &self.columns {
+            if !column.skip && !names.insert(column.name.as_str()) {
+                return Err(anyhow!("Fixed-width column '{}' is declared twice", column.name));
+            }
+            let (start, len) = match (column.start, column.length, column.width) {
+                (None, None, Some(width)) if by_width => (next, width),
+                (Some(start), Some(len), None) if start > 0 && !by_width => (start - 1, len),
+                _ => {
+                    return Err(anyhow!(
+                        "Fixed-width column '{}' needs start (from 1) and length, or a width; \
+                         all columns must use the same style",
+                        column.name
+                    ))
+                }
+            };
+            next = start + len;
+            let pad = match column.pad.as_deref() {
+                None => ' ',
+                Some(pad) => {
+                    let mut chars = pad.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => c,
+                        _ => return Err(anyhow!("Padding of column '{}' must be a single character", column.name)),
+                    }
+                }
+            };
+            if !column.skip {
+                spans.push(Span {
+                    start,
+                    len,
+                    trim: column.trim,
+                    pad,
+                });
+            }
+        }
+        Ok(spans)
+    }
+}
+
+/// Cuts each line of a fixed-width file into the layout's columns, so rows
+/// go through the same mapping as CSV records.
+pub struct FixedSource<R> {
+    reader: R,
+    spans: Vec<Span>,
+    end: RowEnd,
+}
+
+impl<R: BufRead> FixedSource<R> {
+    /// `start` is where `reader` is positioned in the file. Leading lines
+    /// are only skipped when reading from the beginning.
+    pub fn new(reader: R, layout: &FixedLayout, start: RowEnd) -> Result<Self> {
+        let mut source = FixedSource {
+            reader,
+            spans: layout.spans()?,
+            end: RowEnd {
+                line: start.line.max(1),
+                offset: start.offset,
+            },
+        };
+        if start.offset == 0 {
+            for _ in 0..layout.skip_lines {
+                if source.next_line(false)?.is_none() {
+                    break;
+                }
+            }
+        }
+        Ok(source)
+    }
+
+    fn next_line(&mut self, skip_blank: bool) -> Result<Option<Vec<u8>>> {
+        loop {
+            let mut line = Vec::new();
+            let read = self.reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.end.offset += read as u64;
+            self.end.line += 1;
+            while line.last().is_some_and(|b| matches!(b, b'\n' | b'\r')) {
+                line.pop();
+            }
+            if !skip_blank || !line.iter().all(u8::is_ascii_whitespace) {
+                return Ok(Some(line));
+            }
+        }
+    }
+
+    /// Columns past the end of a short line come out short or empty, since
+    /// trailing blanks are often stripped from fixed-width extracts.
+    fn split(&self, line: &[u8]) -> Result<ByteRecord> {
+        let line = std::str::from_utf8(line).map_err(|e| anyhow!("Line is not valid UTF-8: {}", e))?;
+        let bounds: Vec<usize> = line.char_indices().map(|(i, _)| i).chain([line.len()]).collect();
+        let chars = bounds.len() - 1;
+        let mut record = ByteRecord::new();
+        for span in &self.spans {
+            let from = bounds[span.start.min(chars)];
+            let to = bounds[(span.start + span.len).min(chars)];
+            let value = &line[from..to];
+            let value = match span.trim {
+                FixedTrim::Both => value.trim_matches(span.pad),
+                FixedTrim::Left => value.trim_start_matches(span.pad),
+                FixedTrim::Right => value.trim_end_matches(span.pad),
+                FixedTrim::None => value,
+            };
+            record.push_field(value.as_bytes());
+        }
+        Ok(record)
+    }
+}
+
+impl<R: BufRead + Send> RecordSource for FixedSource<R> {
+    fn next_record(&mut self) -> Option<Result<RawRecord>> {
+        let line = match self.next_line(true) {
+            Ok(line) => line?,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(self.split(&line).map(RawRecord::Csv))
+    }
+
+    fn end(&self) -> RowEnd {
+        self.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(yaml: &str) -> FixedLayout {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn rows(text: &str, layout: &FixedLayout) -> Vec<Vec<String>> {
+        let start = RowEnd { line: 0, offset: 0 };
+        let mut source = FixedSource::new(text.as_bytes(), layout, start).unwrap();
+        let mut rows = Vec::new();
+        while let Some(record) = source.next_record() {
+            match record.unwrap() {
+                RawRecord::Csv(record) => {
+                    rows.push(record.iter().map(|f| String::from_utf8(f.to_vec()).unwrap()).collect())
+                }
+                _ => panic!("fixed-width rows are read as CSV records"),
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn widths_and_starts_give_the_same_columns() {
+        let by_width = layout(
+            "columns:\n  - {name: id, width: 3}\n  - {name: gap, width: 1, skip: true}\n  - {name: name, width: 5}\n",
+        );
+        let by_start = layout("columns:\n  - {name: id, start: 1, length: 3}\n  - {name: name, start: 5, length: 5}\n");
+        let text = "001 Alice\n002 Bob\n";
+        assert_eq!(by_width.names(), ["id", "name"]);
+        assert_eq!(rows(text, &by_width), [["001", "Alice"], ["002", "Bob"]]);
+        assert_eq!(rows(text, &by_start), rows(text, &by_width));
+    }
+
+    #[test]
+    fn starts_may_overlap_and_leave_gaps() {
+        let layout = layout(
+            "columns:\n  - {name: date, start: 1, length: 8}\n  - {name: year, start: 1, length: 4}\n  \
+             - {name: code, start: 12, length: 2}\n",
+        );
+        assert_eq!(rows("20240315XXXAB\n", &layout), [["20240315", "2024", "AB"]]);
+    }
+
+    #[test]
+    fn invalid_layouts_are_rejected() {
+        let cases = [
+            ("columns: []\n", "at least one column"),
+            ("columns:\n  - {name: a, width: 2}\n  - {name: b, start: 3, length: 2}\n", "same style"),
+            ("columns:\n  - {name: a, start: 0, length: 2}\n", "from 1"),
+            ("columns:\n  - {name: a, width: 2}\n  - {name: a, width: 2}\n", "declared twice"),
+            ("columns:\n  - {name: a, width: 2, pad: \"ab\"}\n", "single character"),
+        ];
+        for (yaml, message) in cases {
+            let error = FixedSource::new(&b""[..], &layout(yaml), RowEnd { line: 0, offset: 0 })
+                .err()
+                .unwrap()
+                .to_string();
+            assert!(error.contains(message), "{}: {}", yaml, error);
+        }
+        // Skipped filler may reuse a name.
+        let filler = layout("columns:\n  - {name: x, width: 1, skip: true}\n  - {name: x, width: 1, skip: true}\n");
+        assert!(filler.spans().is_ok());
+    }
+
+    #[test]
+    fn padding_is_trimmed_from_the_chosen_side() {
+        let layout = layout(
+            "columns:\n  - {name: both, width: 5, pad: '*'}\n  - {name: left, width: 5, pad: '0', trim: left}\n  \
+             - {name: right, width: 5, trim: right}\n  - {name: none, width: 5, trim: none}\n",
+        );
+        assert_eq!(rows("*ab**00120 x    y  \n", &layout), [["ab", "120", " x", " y  "]]);
+    }
+
+    #[test]
+    fn short_lines_give_short_or_empty_columns() {
+        let layout = layout("columns:\n  - {name: a, width: 3}\n  - {name: b, width: 3}\n  - {name: c, width: 3}\n");
+        assert_eq!(rows("abcd\r\n", &layout), [["abc", "d", ""]]);
+    }
+
+    #[test]
+    fn columns_count_characters_not_bytes() {
+        let layout = layout("columns:\n  - {name: name, width: 5}\n  - {name: city, width: 6}\n");
+        assert_eq!(rows("Zoë  Zürich\n", &layout), [["Zoë", "Zürich"]]);
+        let start = RowEnd { line: 0, offset: 0 };
+        let mut source = FixedSource::new(&b"ab\xff\n"[..], &layout, start).unwrap();
+        assert!(source.next_record().unwrap().is_err());
+    }
+
+    #[test]
+    fn header_lines_are_skipped_only_from_the_start() {
+        let layout = layout("skip_lines: 1\ncolumns:\n  - {name: id, width: 3}\n");
+        let text = "HDR\n001\n\n002\n";
+        assert_eq!(rows(text, &layout), [["001"], ["002"]]);
+
+        let mut source = FixedSource::new(text.as_bytes(), &layout, RowEnd { line: 0, offset: 0 }).unwrap();
+        assert_eq!((source.end().line, source.end().offset), (2, 4));
+        source.next_record().unwrap().unwrap();
+        source.next_record().unwrap().unwrap();
+        assert_eq!((source.end().line, source.end().offset), (5, 13));
+
+        // A resumed reader already sits past the header.
+        let resumed = RowEnd { line: 3, offset: 8 };
+        let mut source = FixedSource::new(&text.as_bytes()[8..], &layout, resumed).unwrap();
+        assert!(matches!(source.next_record(), Some(Ok(RawRecord::Csv(r))) if &r[0] == b"002"));
+        assert!(source.next_record().is_none());
+    }
+
+    #[test]
+    fn the_customer_extract_is_sliced_by_its_layout() {
+        let mapping: serde_yaml::Value =
+            serde_yaml::from_str(&std::fs::read_to_string("tests/mapping/customers-fixed.yaml").unwrap()).unwrap();
+        let layout: FixedLayout = serde_yaml::from_value(mapping["fixed"].clone()).unwrap();
+        let rows = rows(&std::fs::read_to_string("tests/data/customers.dat").unwrap(), &layout);
+        assert_eq!(rows[0], ["123", "Alice Smith", "3400", "20240101", "Y"]);
+        assert_eq!(rows[2], ["125", "Carol", "", "20230315", "Y"]);
+    }
+}
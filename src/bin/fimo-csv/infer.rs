@@ -0,0 +1,443 @@
+// src/infer.rs
+use crate::cli::InferArgs;
+use crate::dialect::{sniff, CsvDialect, SNIFF_BYTES};
+use crate::stream::InputStream;
+use anyhow::Result;
+use bson::oid::ObjectId;
+use bson::{DateTime, Decimal128};
+use chrono::{NaiveDate, NaiveDateTime};
+use csv::StringRecord;
+use std::io::BufRead;
+use std::str::FromStr;
+
+const DEFAULT_SAMPLE_ROWS: usize = 1000;
+
+/// Distinct values shown in the comment above each field.
+const SHOWN_SAMPLES: usize = 3;
+
+const TRUTHY: [&str; 6] = ["true", "t", "yes", "y", "1", "on"];
+const FALSY: [&str; 6] = ["false", "f", "no", "n", "0", "off"];
+
+/// Date formats tried when a value is not RFC 3339, in order of preference.
+/// Day-first comes before month-first, so `01/02/2024` reads as 1 February.
+const DATE_FORMATS: [&str; 16] = [
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d",
+    "%Y/%m/%d %H:%M:%S",
+    "%Y/%m/%d",
+    "%d/%m/%Y %H:%M:%S",
+    "%d/%m/%Y %H:%M",
+    "%d/%m/%Y",
+    "%m/%d/%Y %H:%M:%S",
+    "%m/%d/%Y %H:%M",
+    "%m/%d/%Y",
+    "%d.%m.%Y %H:%M:%S",
+    "%d.%m.%Y",
+    "%d-%b-%Y",
+];
+
+/// What was seen in one column of the sample.
+struct ColumnStats {
+    name: String,
+    rows: usize,
+    empty: usize,
+    values: Vec<String>,
+}
+
+/// A guessed field definition.
+struct Guess {
+    r#type: &'static str,
+    formats: Vec<&'static str>,
+    truthy: Vec<String>,
+    falsy: Vec<String>,
+}
+
+impl Guess {
+    fn of(r#type: &'static str) -> Guess {
+        Guess {
+            r#type,
+            formats: Vec::new(),
+            truthy: Vec::new(),
+            falsy: Vec::new(),
+        }
+    }
+}
+
+/// Samples the input and writes a mapping file guessed from it.
+pub fn run(args: &InferArgs) -> Result<()> {
+    let sample_rows = args.sample_rows.unwrap_or(DEFAULT_SAMPLE_ROWS);
+    let mut reader = InputStream::open(&args.input, SNIFF_BYTES, args.dialect.encoding()?)?.starting_at(0)?;
+
+    let mut dialect = args.dialect.clone();
+    if dialect.sniff_enabled() {
+        dialect = sniff(reader.fill_buf()?).overridden_by(dialect);
+        dialect.sniff = None;
+    }
+
+    let mut rdr = dialect
+        .reader_builder()?
+        .flexible(true)
+        .has_headers(!args.no_header)
+        .from_reader(reader);
+
+    let mut columns: Vec<ColumnStats> = Vec::new();
+    if !args.no_header {
+        for name in rdr.headers()?.iter() {
+            columns.push(ColumnStats::new(name.to_string()));
+        }
+    }
+
+    let mut record = StringRecord::new();
+    let mut sampled = 0;
+    while sampled < sample_rows {
+        match rdr.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            // Rows that cannot be parsed are left out of the sample.
+            Err(_) => continue,
+        }
+        sampled += 1;
+        while columns.len() < record.len() {
+            columns.push(ColumnStats::new(format!("col_{}", columns.len())));
+        }
+        for (i, column) in columns.iter_mut().enumerate() {
+            column.add(record.get(i).unwrap_or(""));
+        }
+    }
+
+    let yaml = render_mapping(&args.input, sampled, &dialect, &columns);
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, yaml)?;
+            println!("✅ Wrote a mapping for {} columns to {}", columns.len(), path);
+        }
+        None => print!("{}", yaml),
+    }
+    Ok(())
+}
+
+impl ColumnStats {
+    fn new(name: String) -> ColumnStats {
+        ColumnStats {
+            name,
+            rows: 0,
+            empty: 0,
+            values: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        self.rows += 1;
+        if value.is_empty() {
+            self.empty += 1;
+        } else {
+            self.values.push(value.to_string());
+        }
+    }
+
+    /// The narrowest type every non-empty value converts to.
+    fn guess(&self) -> Guess {
+        let values = &self.values;
+        if values.is_empty() {
+            return Guess::of("string");
+        }
+        // Leading zeros, as in zip codes or account numbers, are kept as text.
+        let numeric = values.iter().all(|v| !has_leading_zero(v));
+
+        if numeric && values.iter().all(|v| v.parse::<i32>().is_ok()) {
+            return Guess::of("int");
+        }
+        if numeric && values.iter().all(|v| v.parse::<i64>().is_ok()) {
+            return Guess::of("long");
+        }
+        if numeric && values.iter().all(|v| is_plain_number(v)) {
+            return Guess::of(if values.iter().any(|v| needs_decimal(v)) || money_scale(values) {
+                "decimal"
+            } else {
+                "double"
+            });
+        }
+        if let Some(guess) = guess_bool(values) {
+            return guess;
+        }
+        if values.iter().all(|v| v.len() == 24 && ObjectId::parse_str(v).is_ok()) {
+            return Guess::of("objectId");
+        }
+        if values.iter().all(|v| DateTime::parse_rfc3339_str(v).is_ok()) {
+            return Guess::of("date");
+        }
+        if let Some(formats) = date_formats(values) {
+            return Guess {
+                formats,
+                ..Guess::of("date")
+            };
+        }
+        Guess::of("string")
+    }
+
+    /// First distinct values, shortened for the comment.
+    fn shown_samples(&self) -> Vec<String> {
+        let mut shown: Vec<&str> = Vec::new();
+        for value in &self.values {
+            if shown.len() == SHOWN_SAMPLES {
+                break;
+            }
+            if !shown.contains(&value.as_str()) {
+                shown.push(value);
+            }
+        }
+        shown
+            .iter()
+            .map(|value| {
+                let short: String = value.chars().take(30).collect();
+                let short = if short.len() < value.len() { format!("{}…", short) } else { short };
+                serde_json::Value::from(short).to_string()
+            })
+            .collect()
+    }
+}
+
+fn has_leading_zero(value: &str) -> bool {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.")
+}
+
+/// Digits with an optional sign, decimal point and exponent; rejects `inf`
+/// and `NaN`, which `f64` would accept.
+fn is_plain_number(value: &str) -> bool {
+    value.parse::<f64>().is_ok() && value.chars().all(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+}
+
+/// More significant digits than a double keeps.
+fn needs_decimal(value: &str) -> bool {
+    let digits: String = value.chars().take_while(|c| !matches!(c, 'e' | 'E')).filter(char::is_ascii_digit).collect();
+    digits.trim_start_matches('0').len() > 15 && Decimal128::from_str(value).is_ok()
+}
+
+/// Every value has exactly two digits after the point, as amounts of money
+/// do, where a double would turn `0.10` into `0.1000000000000000055…`.
+fn money_scale(values: &[String]) -> bool {
+    values.iter().all(|v| match v.split_once('.') {
+        Some((_, cents)) => cents.len() == 2 && cents.chars().all(|c| c.is_ascii_digit()) && Decimal128::from_str(v).is_ok(),
+        None => false,
+    })
+}
+
+/// A bool column only holds known true and false tokens, in any case. The
+/// tokens seen become the field's `truthy` and `falsy` lists.
+fn guess_bool(values: &[String]) -> Option<Guess> {
+    let mut truthy: Vec<String> = Vec::new();
+    let mut falsy: Vec<String> = Vec::new();
+    for value in values {
+        let lower = value.to_lowercase();
+        let list = if TRUTHY.contains(&lower.as_str()) {
+            &mut truthy
+        } else if FALSY.contains(&lower.as_str()) {
+            &mut falsy
+        } else {
+            return None;
+        };
+        if !list.contains(value) {
+            list.push(value.clone());
+        }
+    }
+    Some(Guess {
+        truthy,
+        falsy,
+        ..Guess::of("bool")
+    })
+}
+
+/// The fewest formats from `DATE_FORMATS` that parse every value, or `None`
+/// when some value matches none of them.
+fn date_formats(values: &[String]) -> Option<Vec<&'static str>> {
+    let parses = |value: &str, format: &str| {
+        NaiveDateTime::parse_from_str(value, format).is_ok() || NaiveDate::parse_from_str(value, format).is_ok()
+    };
+    let mut formats = Vec::new();
+    let mut remaining: Vec<&String> = values.iter().collect();
+    while !remaining.is_empty() {
+        let best = DATE_FORMATS
+            .iter()
+            .filter(|format| !formats.contains(*format))
+            .max_by_key(|format| {
+                let matched = remaining.iter().filter(|v| parses(v, format)).count();
+                // Ties go to the format listed first.
+                (matched, std::cmp::Reverse(DATE_FORMATS.iter().position(|f| f == *format)))
+            })?;
+        let before = remaining.len();
+        remaining.retain(|v| !parses(v, best));
+        if remaining.len() == before {
+            return None;
+        }
+        formats.push(*best);
+    }
+    Some(formats)
+}
+
+/// The mapping file: a comment header, the `csv:` section when the dialect
+/// is not the default, and one commented field per column.
+fn render_mapping(input: &str, sampled: usize, dialect: &CsvDialect, columns: &[ColumnStats]) -> String {
+    let mut out = format!(
+        "# Mapping inferred by fimo-csv from {} ({} rows sampled).\n# Review the types before importing.\n",
+        input, sampled
+    );
+
+    let settings = dialect_settings(dialect);
+    let indent = if settings.is_empty() {
+        ""
+    } else {
+        out.push_str("csv:\n");
+        for (key, value) in settings {
+            out.push_str(&format!("  {}: {}\n", key, value));
+        }
+        out.push_str("fields:\n");
+        "  "
+    };
+
+    for column in columns {
+        let guess = column.guess();
+        let empty = if column.rows == 0 { 0.0 } else { column.empty as f64 * 100.0 / column.rows as f64 };
+        out.push_str(&format!(
+            "{}# samples: {}; empty: {:.1}% ({} of {})\n",
+            indent,
+            match column.shown_samples() {
+                shown if shown.is_empty() => "none".to_string(),
+                shown => shown.join(", "),
+            },
+            empty,
+            column.empty,
+            column.rows
+        ));
+        out.push_str(&format!("{}{}:\n", indent, yaml_scalar(&column.name)));
+        out.push_str(&format!("{}  type: {}\n", indent, guess.r#type));
+        if column.rows > 0 && column.empty == 0 {
+            out.push_str(&format!("{}  required: true\n", indent));
+        } else if column.empty > 0 && guess.r#type != "string" {
+            // An empty cell cannot be converted to a number, date or the like.
+            out.push_str(&format!("{}  on_empty: omit\n", indent));
+        }
+        if !guess.truthy.is_empty() {
+            out.push_str(&format!("{}  truthy: {}\n", indent, yaml_list(&guess.truthy)));
+        }
+        if !guess.falsy.is_empty() {
+            out.push_str(&format!("{}  falsy: {}\n", indent, yaml_list(&guess.falsy)));
+        }
+        if !guess.formats.is_empty() {
+            out.push_str(&format!("{}  formats: {}\n", indent, yaml_list(&guess.formats)));
+        }
+    }
+    out
+}
+
+/// Dialect options that differ from the defaults, as YAML values.
+fn dialect_settings(dialect: &CsvDialect) -> Vec<(&'static str, String)> {
+    let text = [
+        ("delimiter", &dialect.delimiter),
+        ("quote", &dialect.quote),
+        ("escape", &dialect.escape),
+        ("comment", &dialect.comment),
+        ("terminator", &dialect.terminator),
+        ("trim", &dialect.trim),
+        ("encoding", &dialect.encoding),
+    ];
+    let flags = [("double_quote", dialect.double_quote), ("flexible", dialect.flexible)];
+
+    let mut settings: Vec<(&'static str, String)> = text
+        .into_iter()
+        .filter_map(|(key, value)| value.as_ref().map(|v| (key, serde_json::Value::from(v.as_str()).to_string())))
+        .filter(|(key, value)| !matches!((*key, value.as_str()), ("delimiter", r#"",""#) | ("quote", r#""\"""#)))
+        .collect();
+    settings.extend(flags.into_iter().filter_map(|(key, value)| value.map(|v| (key, v.to_string()))));
+    settings
+}
+
+/// A mapping key, quoted only when YAML would otherwise read it differently.
+fn yaml_scalar(value: &str) -> String {
+    serde_yaml::to_string(value)
+        .map(|yaml| yaml.trim_end().to_string())
+        .unwrap_or_else(|_| serde_json::Value::from(value).to_string())
+}
+
+fn yaml_list<S: AsRef<str>>(values: &[S]) -> String {
+    let items: Vec<String> = values.iter().map(|v| serde_json::Value::from(v.as_ref()).to_string()).collect();
+    format!("[{}]", items.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapping::load_mapping;
+    use crate::transform::{apply_mapping, validate_required_fields};
+    use bson::Bson;
+    use std::collections::HashMap;
+
+    fn column(name: &str, values: &[&str]) -> ColumnStats {
+        let mut column = ColumnStats::new(name.to_string());
+        for value in values {
+            column.add(value);
+        }
+        column
+    }
+
+    fn guessed(values: &[&str]) -> Guess {
+        column("c", values).guess()
+    }
+
+    #[test]
+    fn columns_get_the_narrowest_type() {
+        assert_eq!(guessed(&["1", "-2", ""]).r#type, "int");
+        assert_eq!(guessed(&["1", "3000000000"]).r#type, "long");
+        assert_eq!(guessed(&["1.5", "2e3"]).r#type, "double");
+        assert_eq!(guessed(&["1.50", "0.10"]).r#type, "decimal");
+        assert_eq!(guessed(&["3.14159265358979323"]).r#type, "decimal");
+        assert_eq!(guessed(&["00501", "10001"]).r#type, "string");
+        assert_eq!(guessed(&["NaN", "1"]).r#type, "string");
+        assert_eq!(guessed(&["507f1f77bcf86cd799439011"]).r#type, "objectId");
+        assert_eq!(guessed(&["2024-01-01T10:00:00Z"]).r#type, "date");
+        assert_eq!(guessed(&[]).r#type, "string");
+    }
+
+    #[test]
+    fn bools_and_dates_keep_what_was_seen() {
+        let guess = guessed(&["Yes", "No", "yes"]);
+        assert_eq!(guess.r#type, "bool");
+        assert_eq!(guess.truthy, ["Yes", "yes"]);
+        assert_eq!(guess.falsy, ["No"]);
+
+        let guess = guessed(&["15/01/1990", "31/12/1985", "1990-01-15"]);
+        assert_eq!(guess.r#type, "date");
+        assert_eq!(guess.formats, ["%d/%m/%Y", "%Y-%m-%d"]);
+    }
+
+    #[test]
+    fn inferred_mapping_converts_the_sampled_rows() {
+        let rows = [["1", "2024-01-31", "true", "a"], ["", "", "", ""], ["3", "2024-02-01", "false", "c"]];
+        let names = ["count", "day", "active", "label"];
+        let columns: Vec<ColumnStats> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| column(name, &rows.iter().map(|row| row[i]).collect::<Vec<_>>()))
+            .collect();
+
+        let yaml = render_mapping("sample.csv", rows.len(), &CsvDialect::default(), &columns);
+        let mapping = load_mapping(&yaml).unwrap().fields;
+        assert_eq!(mapping.0["count"].r#type, "int");
+        assert_eq!(mapping.0["label"].r#type, "string");
+
+        for (i, row) in rows.iter().enumerate() {
+            let record: HashMap<String, String> =
+                names.iter().zip(row).map(|(name, value)| (name.to_string(), value.to_string())).collect();
+            validate_required_fields(&record, &mapping).unwrap();
+            let doc = apply_mapping(&record, &mapping, i + 1).unwrap();
+            if i == 1 {
+                assert_eq!(doc.keys().collect::<Vec<_>>(), ["label"]);
+                assert_eq!(doc.get("label"), Some(&Bson::String(String::new())));
+            }
+        }
+    }
+}
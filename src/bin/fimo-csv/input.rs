@@ -0,0 +1,577 @@
+// src/input.rs
+use crate::checkpoint::RowEnd;
+use crate::stream::{is_stdin, strip_compression};
+use anyhow::{anyhow, Result};
+use bson::{spec::BinarySubtype, Binary, Bson, DateTime, Decimal128, Document};
+use clap::ValueEnum;
+use csv::{ByteRecord, Reader};
+use parquet::file::reader::FileReader;
+use parquet::file::serialized_reader::{ReadOptionsBuilder, SerializedFileReader};
+use parquet::record::reader::RowIter;
+use parquet::record::{Field, Row};
+use std::fs::File;
+use std::io::{BufRead, Read};
+use std::str::FromStr;
+
+/// The kinds of input fimo-csv can import.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum InputFormat {
+    /// Delimited text, see the CSV dialect options
+    Csv,
+    /// One JSON object per line (JSON Lines / NDJSON)
+    Jsonl,
+    /// A single JSON array of objects
+    JsonArray,
+    /// Apache Parquet, read one row group at a time
+    Parquet,
+    /// Excel workbook (xlsx, xlsm, xlsb or xls)
+    Xlsx,
+    /// OpenDocument spreadsheet
+    Ods,
+    /// Fixed-width text, laid out by the `fixed:` section of the mapping
+    Fixed,
+}
+
+impl InputFormat {
+    /// Guesses the format from the file extension, ignoring a compression
+    /// extension such as `.gz`, and falls back to CSV.
+    pub fn from_path(path: &str) -> InputFormat {
+        let lower = strip_compression(path).to_lowercase();
+        if lower.ends_with(".jsonl") || lower.ends_with(".ndjson") {
+            InputFormat::Jsonl
+        } else if lower.ends_with(".json") {
+            InputFormat::JsonArray
+        } else if lower.ends_with(".parquet") {
+            InputFormat::Parquet
+        } else if [".xlsx", ".xlsm", ".xlsb", ".xls"].iter().any(|ext| lower.ends_with(ext)) {
+            InputFormat::Xlsx
+        } else if lower.ends_with(".ods") {
+            InputFormat::Ods
+        } else {
+            InputFormat::Csv
+        }
+    }
+}
+
+/// Expands `--input` values into the files to import. Globs are matched in
+/// sorted order; files given by name keep the order they were given in, and
+/// a file named twice is imported once.
+pub fn expand_inputs(patterns: &[String]) -> Result<Vec<String>> {
+    let mut inputs: Vec<String> = Vec::new();
+    for pattern in patterns {
+        let matched = if is_stdin(pattern) || !pattern.contains(['*', '?', '[']) {
+            vec![pattern.clone()]
+        } else {
+            let mut paths = glob::glob(pattern)
+                .map_err(|e| anyhow!("Invalid input pattern '{}': {}", pattern, e))?
+                .map(|path| Ok(path?.to_string_lossy().into_owned()))
+                .collect::<Result<Vec<String>>>()?;
+            if paths.is_empty() {
+                return Err(anyhow!("No input files match '{}'", pattern));
+            }
+            paths.sort();
+            paths
+        };
+        for path in matched {
+            if !inputs.contains(&path) {
+                inputs.push(path);
+            }
+        }
+    }
+    if inputs.len() > 1 && inputs.iter().any(|input| is_stdin(input)) {
+        return Err(anyhow!("stdin (-) cannot be combined with other inputs"));
+    }
+    Ok(inputs)
+}
+
+/// A record as read from the input, before it is decoded.
+pub enum RawRecord {
+    Csv(ByteRecord),
+    /// The text of one JSON value, not yet parsed.
+    Json(Vec<u8>),
+    /// A record that was already decoded, such as a Parquet or spreadsheet row.
+    Document(Document),
+    /// Nothing could be read for this row.
+    Unreadable,
+}
+
+/// Reads records one at a time, on the pipeline's reader thread.
+pub trait RecordSource: Send {
+    /// Reads the next record, or `None` at the end of the input.
+    fn next_record(&mut self) -> Option<Result<RawRecord>>;
+
+    /// Where the record last returned ends.
+    fn end(&self) -> RowEnd;
+}
+
+pub struct CsvSource<R> {
+    rdr: Reader<R>,
+}
+
+impl<R: Read> CsvSource<R> {
+    pub fn new(rdr: Reader<R>) -> Self {
+        CsvSource { rdr }
+    }
+}
+
+impl<R: Read + Send> RecordSource for CsvSource<R> {
+    fn next_record(&mut self) -> Option<Result<RawRecord>> {
+        let mut raw = ByteRecord::new();
+        match self.rdr.read_byte_record(&mut raw) {
+            Ok(false) => None,
+            Ok(true) => Some(Ok(RawRecord::Csv(raw))),
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+
+    fn end(&self) -> RowEnd {
+        let position = self.rdr.position();
+        RowEnd {
+            line: position.line(),
+            offset: position.byte(),
+        }
+    }
+}
+
+/// Reads JSON Lines, or the elements of a top-level JSON array, without
+/// loading the whole input. Values are split here and parsed by the workers.
+pub struct JsonSource<R> {
+    reader: R,
+    array: bool,
+    state: ArrayState,
+    end: RowEnd,
+}
+
+#[derive(PartialEq)]
+enum ArrayState {
+    BeforeArray,
+    InArray,
+    Done,
+}
+
+impl<R: BufRead> JsonSource<R> {
+    /// `start` is where `reader` is positioned in the file. When resuming a
+    /// JSON array the reader is already past the opening bracket.
+    pub fn new(reader: R, array: bool, start: RowEnd) -> Self {
+        let state = if start.offset > 0 { ArrayState::InArray } else { ArrayState::BeforeArray };
+        JsonSource {
+            reader,
+            array,
+            state,
+            end: RowEnd {
+                line: start.line.max(1),
+                offset: start.offset,
+            },
+        }
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        let byte = match self.reader.fill_buf()?.first() {
+            Some(byte) => *byte,
+            None => return Ok(None),
+        };
+        self.reader.consume(1);
+        self.end.offset += 1;
+        if byte == b'\n' {
+            self.end.line += 1;
+        }
+        Ok(Some(byte))
+    }
+
+    fn peek_byte(&mut self) -> Result<Option<u8>> {
+        Ok(self.reader.fill_buf()?.first().copied())
+    }
+
+    fn next_line(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            let mut line = Vec::new();
+            let read = self.reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.end.offset += read as u64;
+            self.end.line += 1;
+            while line.last().is_some_and(|b| matches!(b, b'\n' | b'\r')) {
+                line.pop();
+            }
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                return Ok(Some(line));
+            }
+        }
+    }
+
+    /// Skips whitespace and separators up to the next element of the array.
+    fn next_element(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.state == ArrayState::BeforeArray {
+            match self.skip_whitespace()? {
+                Some(b'[') => {
+                    self.next_byte()?;
+                    self.state = ArrayState::InArray;
+                }
+                Some(other) => return Err(anyhow!("Expected a JSON array, found '{}'", other as char)),
+                None => return Err(anyhow!("Expected a JSON array, found an empty input")),
+            }
+        }
+
+        loop {
+            match self.skip_whitespace()? {
+                Some(b',') => {
+                    self.next_byte()?;
+                }
+                Some(b']') => {
+                    self.next_byte()?;
+                    self.state = ArrayState::Done;
+                    return Ok(None);
+                }
+                Some(_) => break,
+                None => return Err(anyhow!("Unexpected end of input inside the JSON array")),
+            }
+        }
+
+        let mut value = Vec::new();
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        while let Some(byte) = self.peek_byte()? {
+            if !in_string && depth == 0 && !value.is_empty() && matches!(byte, b',' | b']') {
+                break;
+            }
+            self.next_byte()?;
+            value.push(byte);
+            if in_string {
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'"' => in_string = true,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            if depth == 0 && matches!(byte, b'}' | b']') {
+                break;
+            }
+        }
+        // Scalars run up to the next separator, whitespace included.
+        while value.last().is_some_and(u8::is_ascii_whitespace) {
+            value.pop();
+        }
+        Ok(Some(value))
+    }
+
+    fn skip_whitespace(&mut self) -> Result<Option<u8>> {
+        while let Some(byte) = self.peek_byte()? {
+            if !byte.is_ascii_whitespace() {
+                return Ok(Some(byte));
+            }
+            self.next_byte()?;
+        }
+        Ok(None)
+    }
+}
+
+impl<R: BufRead + Send> RecordSource for JsonSource<R> {
+    fn next_record(&mut self) -> Option<Result<RawRecord>> {
+        let next = if !self.array {
+            self.next_line()
+        } else if self.state == ArrayState::Done {
+            Ok(None)
+        } else {
+            self.next_element().inspect_err(|_| self.state = ArrayState::Done)
+        };
+        next.transpose().map(|value| value.map(RawRecord::Json))
+    }
+
+    fn end(&self) -> RowEnd {
+        self.end
+    }
+}
+
+/// Reads Parquet rows straight into BSON documents. Only the row group being
+/// read is held in memory.
+pub struct ParquetSource {
+    rows: RowIter<'static>,
+    /// Rows read from the start of the file, used as the checkpoint offset.
+    read: u64,
+}
+
+impl ParquetSource {
+    /// Opens `file` positioned after its first `start` rows. Row groups that
+    /// end before `start` are not read at all.
+    pub fn open(file: File, start: u64) -> Result<Self> {
+        let metadata = SerializedFileReader::new(file.try_clone()?)?.metadata().clone();
+        let mut skipped_groups = 0;
+        let mut read = 0u64;
+        for group in metadata.row_groups() {
+            let rows = group.num_rows() as u64;
+            if read + rows > start {
+                break;
+            }
+            read += rows;
+            skipped_groups += 1;
+        }
+
+        let options = ReadOptionsBuilder::new()
+            .with_predicate(Box::new(move |_, index| index >= skipped_groups))
+            .build();
+        let reader: Box<dyn FileReader> = Box::new(SerializedFileReader::new_with_options(file, options)?);
+        let mut source = ParquetSource {
+            rows: RowIter::from_file_into(reader),
+            read,
+        };
+        while source.read < start {
+            match source.rows.next() {
+                Some(row) => {
+                    row?;
+                    source.read += 1;
+                }
+                None => break,
+            }
+        }
+        Ok(source)
+    }
+}
+
+impl RecordSource for ParquetSource {
+    fn next_record(&mut self) -> Option<Result<RawRecord>> {
+        let row = self.rows.next()?;
+        self.read += 1;
+        Some(row.map(|row| RawRecord::Document(row_to_document(&row))).map_err(Into::into))
+    }
+
+    fn end(&self) -> RowEnd {
+        RowEnd {
+            line: self.read,
+            offset: self.read,
+        }
+    }
+}
+
+fn row_to_document(row: &Row) -> Document {
+    row.get_column_iter()
+        .map(|(name, field)| (name.clone(), field_to_bson(field)))
+        .collect()
+}
+
+/// Maps a Parquet value onto the closest BSON type. Logical types are kept:
+/// timestamps and dates become dates, decimals stay decimal and binary stays
+/// binary.
+fn field_to_bson(field: &Field) -> Bson {
+    match field {
+        Field::Null => Bson::Null,
+        Field::Bool(b) => Bson::Boolean(*b),
+        Field::Byte(n) => Bson::Int32(*n as i32),
+        Field::Short(n) => Bson::Int32(*n as i32),
+        Field::Int(n) => Bson::Int32(*n),
+        Field::Long(n) => Bson::Int64(*n),
+        Field::UByte(n) => Bson::Int32(*n as i32),
+        Field::UShort(n) => Bson::Int32(*n as i32),
+        Field::UInt(n) => Bson::Int64(*n as i64),
+        Field::ULong(n) => match i64::try_from(*n) {
+            Ok(n) => Bson::Int64(n),
+            Err(_) => Decimal128::from_str(&n.to_string()).map(Bson::Decimal128).unwrap_or(Bson::Double(*n as f64)),
+        },
+        Field::Float16(n) => Bson::Double(n.to_f64()),
+        Field::Float(n) => Bson::Double(*n as f64),
+        Field::Double(n) => Bson::Double(*n),
+        Field::Decimal(_) => {
+            let text = field.to_string();
+            Decimal128::from_str(&text).map(Bson::Decimal128).unwrap_or(Bson::String(text))
+        }
+        Field::Str(s) => Bson::String(s.clone()),
+        Field::Bytes(bytes) => Bson::Binary(Binary {
+            subtype: BinarySubtype::Generic,
+            bytes: bytes.data().to_vec(),
+        }),
+        Field::Date(days) => Bson::DateTime(DateTime::from_millis(*days as i64 * 86_400_000)),
+        Field::TimestampMillis(ms) => Bson::DateTime(DateTime::from_millis(*ms)),
+        Field::TimestampMicros(us) => Bson::DateTime(DateTime::from_millis(us.div_euclid(1000))),
+        // BSON has no time of day type.
+        Field::TimeMillis(_) | Field::TimeMicros(_) => Bson::String(field.to_string()),
+        Field::Group(row) => Bson::Document(row_to_document(row)),
+        Field::ListInternal(list) => Bson::Array(list.elements().iter().map(field_to_bson).collect()),
+        Field::MapInternal(map) => Bson::Document(
+            map.entries()
+                .iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Field::Str(s) => s.clone(),
+                        other => other.to_string(),
+                    };
+                    (key, field_to_bson(value))
+                })
+                .collect(),
+        ),
+    }
+}
+
+/// Removes whitespace outside of strings, so a JSON value fits on one line.
+pub fn compact_json(value: &[u8]) -> Vec<u8> {
+    let mut compact = Vec::with_capacity(value.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in value {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+        } else if byte == b'"' {
+            in_string = true;
+        } else if byte.is_ascii_whitespace() {
+            continue;
+        }
+        compact.push(byte);
+    }
+    compact
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn formats_follow_the_extension_under_compression() {
+        assert_eq!(InputFormat::from_path("rows.jsonl.gz"), InputFormat::Jsonl);
+        assert_eq!(InputFormat::from_path("rows.NDJSON"), InputFormat::Jsonl);
+        assert_eq!(InputFormat::from_path("rows.json.zst"), InputFormat::JsonArray);
+        assert_eq!(InputFormat::from_path("rows.parquet"), InputFormat::Parquet);
+        assert_eq!(InputFormat::from_path("book.XLSM"), InputFormat::Xlsx);
+        assert_eq!(InputFormat::from_path("book.ods"), InputFormat::Ods);
+        assert_eq!(InputFormat::from_path("rows.tsv.bz2"), InputFormat::Csv);
+        assert_eq!(InputFormat::from_path("-"), InputFormat::Csv);
+    }
+
+    #[test]
+    fn globs_expand_sorted_and_inputs_are_imported_once() {
+        let inputs = expand_inputs(&strings(&["tests/data/nulls.csv", "tests/data/n*.csv", "tests/data/a*.csv"])).unwrap();
+        assert_eq!(inputs, ["tests/data/nulls.csv", "tests/data/nested.csv", "tests/data/arrays.csv"]);
+    }
+
+    #[test]
+    fn unmatched_globs_and_stdin_with_other_inputs_are_refused() {
+        assert!(expand_inputs(&strings(&["tests/data/*.missing"])).is_err());
+        assert!(expand_inputs(&strings(&["-", "tests/data/nulls.csv"])).is_err());
+        assert_eq!(expand_inputs(&strings(&["-", "-"])).unwrap(), ["-"]);
+        // Names without wildcards are passed on, so a missing file is reported when opened.
+        assert_eq!(expand_inputs(&strings(&["missing.csv"])).unwrap(), ["missing.csv"]);
+    }
+
+    fn json_records(input: &str, array: bool) -> Vec<String> {
+        let start = RowEnd { line: 0, offset: 0 };
+        let mut source = JsonSource::new(input.as_bytes(), array, start);
+        let mut records = Vec::new();
+        while let Some(record) = source.next_record() {
+            match record {
+                Ok(RawRecord::Json(text)) => records.push(String::from_utf8(text).unwrap()),
+                Ok(_) => unreachable!(),
+                Err(e) => records.push(format!("error: {}", e)),
+            }
+        }
+        records
+    }
+
+    #[test]
+    fn array_elements_are_split_outside_strings() {
+        let input = "[\n  {\"a\": \"x],y\", \"b\": [1, [2, 3]]},\n  {\"c\": \"say \\\"hi\\\", ok\\\\\"}\n , [4,5], 6, \"]\" ]";
+        assert_eq!(
+            json_records(input, true),
+            [
+                r#"{"a": "x],y", "b": [1, [2, 3]]}"#,
+                r#"{"c": "say \"hi\", ok\\"}"#,
+                "[4,5]",
+                "6",
+                r#""]""#,
+            ]
+        );
+        assert!(json_records("[]", true).is_empty());
+    }
+
+    #[test]
+    fn broken_arrays_end_with_one_error() {
+        assert_eq!(json_records("{\"a\": 1}", true), ["error: Expected a JSON array, found '{'"]);
+        assert_eq!(json_records(" ", true), ["error: Expected a JSON array, found an empty input"]);
+        assert_eq!(
+            json_records("[{\"a\": 1},", true),
+            [r#"{"a": 1}"#, "error: Unexpected end of input inside the JSON array"]
+        );
+    }
+
+    #[test]
+    fn json_lines_skip_blank_lines_and_track_the_end() {
+        let input = "{\"a\": 1}\r\n\n  \n{\"b\": [\"]\"]}";
+        let mut source = JsonSource::new(input.as_bytes(), false, RowEnd { line: 0, offset: 0 });
+        let Some(Ok(RawRecord::Json(first))) = source.next_record() else { panic!("no first record") };
+        assert_eq!(first, br#"{"a": 1}"#);
+        assert_eq!((source.end().line, source.end().offset), (2, 10));
+        let Some(Ok(RawRecord::Json(second))) = source.next_record() else { panic!("no second record") };
+        assert_eq!(second, br#"{"b": ["]"]}"#);
+        assert_eq!((source.end().line, source.end().offset), (5, input.len() as u64));
+        assert!(source.next_record().is_none());
+    }
+
+    #[test]
+    fn resumed_arrays_start_inside_the_array() {
+        let rest = ", {\"b\": 2}]";
+        let mut source = JsonSource::new(rest.as_bytes(), true, RowEnd { line: 3, offset: 40 });
+        let Some(Ok(RawRecord::Json(record))) = source.next_record() else { panic!("no record") };
+        assert_eq!(record, br#"{"b": 2}"#);
+        assert_eq!(source.end().offset, 40 + rest.len() as u64 - 1);
+        assert!(source.next_record().is_none());
+    }
+
+    fn parquet_rows(start: u64) -> Vec<Document> {
+        let mut source = ParquetSource::open(File::open("tests/data/people.parquet").unwrap(), start).unwrap();
+        let mut rows = Vec::new();
+        while let Some(record) = source.next_record() {
+            let Ok(RawRecord::Document(doc)) = record else { panic!("unreadable Parquet row") };
+            rows.push(doc);
+        }
+        rows
+    }
+
+    #[test]
+    fn parquet_rows_keep_their_types() {
+        let rows = parquet_rows(0);
+        assert_eq!(rows.len(), 3);
+        let alice = &rows[0];
+        assert_eq!(alice.get("age"), Some(&Bson::Int64(34)));
+        assert_eq!(alice.get_datetime("joined").unwrap().try_to_rfc3339_string().unwrap(), "2024-01-01T10:00:00Z");
+        assert_eq!(alice.get("price"), Some(&Bson::Decimal128(Decimal128::from_str("19.99").unwrap())));
+        assert_eq!(alice.get_binary_generic("photo").unwrap(), &vec![255, 0, 16]);
+        assert_eq!(alice.get_document("address").unwrap(), &bson::doc! { "zip": "01234", "city": "Oslo" });
+        assert_eq!(alice.get_array("tags").unwrap(), &vec![Bson::from("a"), Bson::from("b"), Bson::from("a")]);
+        assert_eq!(rows[1].get("joined"), Some(&Bson::Null));
+    }
+
+    #[test]
+    fn parquet_resumes_after_the_rows_already_read() {
+        let rows = parquet_rows(2);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get_str("name").unwrap(), "Carol");
+        assert!(parquet_rows(5).is_empty());
+    }
+
+    #[test]
+    fn parquet_values_map_to_the_closest_bson_type() {
+        assert_eq!(field_to_bson(&Field::UInt(u32::MAX)), Bson::Int64(u32::MAX as i64));
+        assert_eq!(
+            field_to_bson(&Field::ULong(u64::MAX)),
+            Bson::Decimal128(Decimal128::from_str("18446744073709551615").unwrap())
+        );
+        assert_eq!(field_to_bson(&Field::Date(1)), Bson::DateTime(DateTime::from_millis(86_400_000)));
+        assert_eq!(field_to_bson(&Field::TimestampMicros(-1)), Bson::DateTime(DateTime::from_millis(-1)));
+        assert_eq!(field_to_bson(&Field::TimeMillis(1_000)), Bson::String(Field::TimeMillis(1_000).to_string()));
+    }
+}
@@ -1,227 +1,537 @@
+mod checkpoint;
 mod cli;
+mod dialect;
+mod encoding;
+mod fixed;
+mod infer;
+mod input;
 mod mapping;
 mod mongo;
+#[path = "../../common/mongo_version.rs"]
+mod mongo_version;
+mod path;
+mod pipeline;
+mod reject;
+mod report;
+mod schema;
+mod sheet;
+mod stream;
 mod template;
 mod transform;
+mod validate;
 
-use crate::cli::Cli;
-use crate::mapping::{requires_extended_json, FieldMapping};
-use crate::mongo::connect;
-use crate::template::load_templates;
-use crate::transform::{apply_mapping, render_operation, validate_required_fields};
+use crate::checkpoint::{file_sha256, CheckpointTracker, ResumeCheckpoint, RowEnd};
+use crate::cli::{Cli, Command};
+use crate::dialect::{sniff, CsvDialect, SNIFF_BYTES};
+use crate::fixed::{FixedLayout, FixedSource};
+use crate::input::{expand_inputs, CsvSource, InputFormat, JsonSource, ParquetSource, RecordSource};
+use crate::mapping::{load_mapping, requires_extended_json};
+use crate::mongo::{connect, create_indexes, index_model, BatchWriter};
+use crate::mongo_version::is_mongo_8_or_higher;
+use crate::pipeline::{CsvColumns, PipelineOptions, RowContext};
+use crate::reject::{RejectWriter, REJECT_COLUMNS};
+use crate::sheet::{SheetSelection, SheetSource};
+use crate::stream::{is_stdin, open_plain_file, InputStream, READ_BUFFER_BYTES};
+use crate::report::{ImportSummary, EXIT_OK};
+use crate::template::{load_templates, template_environment};
+use crate::transform::{Operation, OPERATIONS, OP_EXPR_TEMPLATE};
+use crate::validate::{check_headers, validate_mapping, Issue, Severity};
 
 use anyhow::{anyhow, Result};
-use bson::{Bson, Document};
+use bson::oid::ObjectId;
+use bson::Document;
 use clap::Parser;
-use csv::ReaderBuilder;
-use mongodb::{
-    options::InsertOneModel, options::UpdateOneModel, options::WriteModel, Collection,
-};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
+use csv::{ByteRecord, Position, Reader, StringRecord};
+use mongodb::Collection;
+use std::io::BufRead;
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Cli::parse();
-
-    let file = File::open(&args.input)?;
-    let reader = BufReader::new(file);
-    let mut rdr = ReaderBuilder::new()
-        .delimiter(b',')
-        .has_headers(!args.no_header)
-        .from_reader(reader);
+    match &args.command {
+        Some(Command::Infer(infer)) => return infer::run(infer),
+        Some(Command::Schema(schema)) => return schema::run(schema).await,
+        None => {}
+    }
 
     let mapping_text = std::fs::read_to_string(&args.mapping)?;
-    let field_mapping: FieldMapping = serde_yaml::from_str(&mapping_text)?;
+    let mapping_file = load_mapping(&mapping_text).map_err(|e| anyhow!("{}: {}", args.mapping, e))?;
+    let field_mapping = mapping_file.fields;
+    report_mapping_issues(&args.mapping, validate_mapping(&field_mapping, &mapping_text), args.strict);
+    for index in &mapping_file.indexes {
+        index_model(index).map_err(|e| anyhow!("{}: {}", args.mapping, e))?;
+    }
 
-    if !args.extended_json && requires_extended_json(&field_mapping) {
+    let inputs = expand_inputs(&args.input)?;
+    let format = match args.format {
+        Some(format) => format,
+        None => single_format(&inputs),
+    };
+
+    // JSON, Parquet and spreadsheet input always keep their BSON types.
+    let extended_json = args.extended_json || format != InputFormat::Csv;
+    if !extended_json && requires_extended_json(&field_mapping) {
         eprintln!(
             "❗️ Error: BSON types detected in mapping file, but --extended-json was not provided."
         );
         std::process::exit(1);
     }
 
-    let env = if let Some(dir) = &args.template_dir {
+    let mut env = if let Some(dir) = &args.template_dir {
         load_templates(dir)?
     } else {
-        minijinja::Environment::new()
+        template_environment(Default::default())
+    };
+
+    // --op-column, --op-expr and --operation exclude each other; any of them
+    // replaces the routing in the mapping file.
+    let (op_column, op_expr) = match (&args.op_column, &args.op_expr, &args.operation) {
+        (None, None, Some(_)) => (None, None),
+        (None, None, None) => (mapping_file.op_column.clone(), mapping_file.op_expr.clone()),
+        (column, expr, _) => (column.clone(), expr.clone()),
+    };
+    let operation = match (op_column, op_expr) {
+        (Some(_), Some(_)) => {
+            eprintln!("❗️ Error: {} sets both op_column and op_expr; use one.", args.mapping);
+            std::process::exit(1);
+        }
+        (Some(column), None) => Operation::Column(column),
+        (None, Some(expr)) => {
+            env.add_template_owned(OP_EXPR_TEMPLATE, expr).map_err(|e| anyhow!("op_expr: {}", e))?;
+            Operation::Expr
+        }
+        (None, None) => {
+            let name = args.operation.clone().unwrap_or_else(|| "insert".to_string());
+            if !OPERATIONS.contains(&name.as_str()) && env.get_template(&name).is_err() {
+                eprintln!(
+                    "❗️ Error: Unknown operation '{}'. Use one of {}, or a template rendering a list of operations.",
+                    name,
+                    OPERATIONS.join(", ")
+                );
+                std::process::exit(1);
+            }
+            Operation::Fixed(name)
+        }
+    };
+    for (value, name) in &mapping_file.op_map {
+        if !OPERATIONS.contains(&name.as_str()) && env.get_template(name).is_err() {
+            eprintln!(
+                "❗️ Error: op_map sends '{}' to '{}', which is neither an operation nor a template.",
+                value, name
+            );
+            std::process::exit(1);
+        }
+    }
+    let routing_column = match &operation {
+        Operation::Column(column) => Some(column.as_str()),
+        _ => None,
     };
+    let env = Arc::new(env);
+    let field_mapping = Arc::new(field_mapping);
 
     let collection: Collection<Document> =
         connect(&args.mongo_uri, &args.db, &args.collection).await?;
 
-    let mut bulk_buffer: Vec<WriteModel> = Vec::new();
     let batch_size = args.batch_size.unwrap_or(0);
 
-    let operation = args.operation.as_deref().unwrap_or("insert");
-    let strip_set_on_insert = operation == "update";
+    let writer = if args.dry_run || args.validate_only {
+        None
+    } else {
+        Some(BatchWriter {
+            client: collection.client().clone(),
+            bulk_write: batch_size > 0 && server_supports_bulk_write(&collection).await?,
+            single: batch_size == 0,
+            ordered: !args.unordered,
+            concurrency: args.concurrency.unwrap_or(10),
+        })
+    };
 
-    let headers = if !args.no_header {
-        Some(rdr.headers()?.clone())
+    // Indexes declared in the mapping are in place before the first write.
+    if writer.is_some() {
+        for index in create_indexes(&collection, &mapping_file.indexes).await? {
+            println!("🗂️ Index {} is in place", index);
+        }
+    }
+
+    let hashes = input_hashes(&args, &inputs)?;
+    let mut carried = if args.resume {
+        resume_checkpoint(&args, &inputs, &hashes)?
     } else {
-        None
+        ResumeCheckpoint::default()
     };
-    
-    let mut row_num = 0;
-    for result in rdr.records() {
-        row_num += 1;
-        let result = match result {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("Row {}: CSV read error: {}", row_num, e);
-                continue;
-            }
+
+    let dialect = mapping_file.csv.clone().overridden_by(args.dialect.clone());
+    let run_id = args.run_id.clone().unwrap_or_else(|| ObjectId::new().to_hex());
+    let mut summary = ImportSummary::start(&run_id);
+    let mut rejects = None;
+
+    for (file, sha256) in inputs.iter().zip(&hashes) {
+        if !sha256.is_empty() && carried.completed.iter().any(|done| &done.sha256 == sha256) {
+            println!("Skipping {}, already imported", file);
+            continue;
+        }
+        if inputs.len() > 1 {
+            println!("📄 {}", file);
+        }
+
+        let mut start = ResumeCheckpoint {
+            input: file.clone(),
+            sha256: sha256.clone(),
+            completed: carried.completed.clone(),
+            ..ResumeCheckpoint::default()
         };
-    
-        let record: HashMap<String, String> = if args.no_header {
-            result
-                .iter()
-                .enumerate()
-                .map(|(i, val)| (format!("col_{}", i), val.to_string()))
-                .collect()
-        } else {
-            match &headers {
-                Some(hdrs) => hdrs
-                    .iter()
-                    .zip(result.iter())
-                    .map(|(k, v)| (k.to_string(), v.to_string()))
-                    .collect(),
-                None => {
-                    eprintln!("Row {}: Header extraction failed", row_num);
-                    continue;
+        if !sha256.is_empty() && &carried.sha256 == sha256 {
+            match format {
+                InputFormat::Csv | InputFormat::Jsonl | InputFormat::JsonArray | InputFormat::Fixed => {
+                    println!("Resuming after row {} (byte offset {})", carried.row, carried.offset)
                 }
+                _ => println!("Resuming after row {}", carried.row),
             }
-        };
-
-        if let Err(e) = validate_required_fields(&record, &field_mapping) {
-            eprintln!("Row {}: {}", row_num, e);
-            continue;
+            (start.row, start.line, start.offset) = (carried.row, carried.line, carried.offset);
         }
 
-        let mapped = match apply_mapping(&record, &field_mapping, row_num) {
-            Ok(doc) => doc,
-            Err(e) => {
-                eprintln!("{}", e);
-                continue;
+        let input = match format {
+            InputFormat::Csv => open_csv(&args, file, dialect.clone(), &start)?,
+            InputFormat::Jsonl | InputFormat::JsonArray => open_json(file, format, &dialect, &start)?,
+            InputFormat::Parquet => open_parquet(file, &start)?,
+            InputFormat::Xlsx | InputFormat::Ods => open_sheet(&args, file, &start)?,
+            InputFormat::Fixed => {
+                let Some(layout) = &mapping_file.fixed else {
+                    eprintln!("❗️ Error: --format fixed needs a `fixed:` section in {}.", args.mapping);
+                    std::process::exit(1);
+                };
+                open_fixed(&args, file, layout, &dialect, &start)?
             }
         };
-
-        if args.validate_only {
-            continue;
+        if let Some(names) = input.columns.names() {
+            let issues = check_headers(&field_mapping, &names, routing_column, &mapping_text);
+            report_mapping_issues(&args.mapping, issues, args.strict);
+        }
+        // One reject file for the whole run, laid out like the first input.
+        if let (Some(path), None) = (&args.reject_file, &rejects) {
+            rejects = Some(input.rejects.create(path, args.resume)?);
         }
 
-        let rendered_json = match render_operation(&env, operation, &mapped, args.raw_insert) {
-            Ok(Some(doc)) => doc,
-            Ok(None) => continue,
-            Err(e) => {
-                eprintln!("Row {}: Template error: {}", row_num, e);
-                continue;
-            }
+        let start_row = start.row;
+        // Checkpoints only move when rows are actually written.
+        let mut checkpoint = match (&args.checkpoint_file, &writer) {
+            (Some(path), Some(_)) => Some(CheckpointTracker::new(path, start)),
+            _ => None,
         };
 
-        let mut rendered: Document = if args.extended_json {
-            match bson::to_bson(&rendered_json) {
-                Ok(Bson::Document(doc)) => doc,
-                _ => {
-                    eprintln!("Row {}: Rendered JSON is not a document", row_num);
-                    continue;
-                }
-            }
-        } else {
-            match serde_json::to_string(&rendered_json)
-                .and_then(|s| serde_json::from_str::<serde_json::Value>(&s))
-            {
-                Ok(json_val) => match bson::to_document(&json_val) {
-                    Ok(doc) => doc,
-                    Err(e) => {
-                        eprintln!("Row {}: JSON to BSON error: {}", row_num, e);
-                        continue;
-                    }
-                },
-                Err(e) => {
-                    eprintln!("Row {}: Template render error: {}", row_num, e);
-                    continue;
-                }
-            }
+        let ctx = RowContext {
+            columns: input.columns,
+            field_mapping: field_mapping.clone(),
+            env: env.clone(),
+            operation: operation.clone(),
+            op_map: mapping_file.op_map.clone(),
+            raw_insert: args.raw_insert,
+            extended_json,
+            validate_only: args.validate_only,
+            file: file.clone(),
+            run_id: run_id.clone(),
+            provenance: args.provenance.clone(),
+        };
+        let options = PipelineOptions {
+            workers: args
+                .workers
+                .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)),
+            max_inflight_batches: args.max_inflight_batches.unwrap_or(4),
+            batch_size,
+            ordered: !args.unordered,
+            dry_run: args.dry_run,
+            debug: args.debug,
+            max_errors: args.max_errors,
+            namespace: collection.namespace(),
+            start_row,
         };
 
-        if strip_set_on_insert {
-            if let Some(update_doc) = rendered.get_document_mut("update").ok() {
-                update_doc.remove("$setOnInsert");
-            }
-        }
+        summary.begin_file(file);
+        pipeline::run(input.source, ctx, options, writer.clone(), &mut checkpoint, &mut rejects, &mut summary).await?;
+        summary.end_file();
 
-        if args.dry_run || args.debug {
-            println!("Row {}: {:?}", row_num, rendered);
+        if summary.exceeds(args.max_errors) {
+            break;
         }
+        if let Some(tracker) = checkpoint {
+            carried = tracker.complete()?;
+        }
+    }
 
-        if !args.dry_run {
-            if batch_size > 0 {
-                let model = match operation {
-                    "insert" => WriteModel::InsertOne(
-                        InsertOneModel::builder()
-                            .namespace(collection.namespace())
-                            .document(rendered)
-                            .build(),
-                    ),
-                    "upsert" | "update" => {
-                        let filter = rendered.get_document("filter").cloned().unwrap_or_default();
-                        let update = rendered.get_document("update").cloned().unwrap_or_default();
-                        WriteModel::UpdateOne(
-                            UpdateOneModel::builder()
-                                .namespace(collection.namespace())
-                                .filter(filter)
-                                .update(update)
-                                .upsert(operation == "upsert")
-                                .build(),
-                        )
-                    }
-                    _ => {
-                        eprintln!("Row {}: Unsupported operation '{}'.", row_num, operation);
-                        continue;
-                    }
-                };
+    if let Some(writer) = &mut rejects {
+        writer.flush()?;
+    }
 
-                bulk_buffer.push(model);
+    if summary.exceeds(args.max_errors) {
+        summary.abort();
+    }
+    summary.finish();
+    summary.print();
+    if let Some(path) = &args.report {
+        summary.write_json(path)?;
+    }
 
-                if bulk_buffer.len() >= batch_size {
-                    let client = collection.client();
-                    let ops: Vec<WriteModel> = bulk_buffer.drain(..).collect();
-                    if let Err(e) = client.bulk_write(ops).await {
-                        eprintln!("Bulk write error at row {}: {}", row_num, e);
-                    }
-                }
-            } else {
-                let result = match operation {
-                    "insert" => collection.insert_one(rendered).await.map(|_| ()),
-                    "upsert" | "update" => {
-                        let filter = rendered.get_document("filter").cloned().unwrap_or_default();
-                        let update = rendered.get_document("update").cloned().unwrap_or_default();
-                        collection
-                            .update_one(filter, update)
-                            .upsert(operation == "upsert")
-                            .await
-                            .map(|_| ())
-                    }
-                    _ => return Err(anyhow!("Unsupported operation: {}", operation)),
-                };
+    if summary.exit_code != EXIT_OK {
+        std::process::exit(summary.exit_code);
+    }
+    Ok(())
+}
+
+/// An opened input, ready for the pipeline.
+struct Input {
+    source: Box<dyn RecordSource>,
+    columns: CsvColumns,
+    rejects: RejectFormat,
+}
 
-                if let Err(e) = result {
-                    eprintln!("Row {}: MongoDB write error: {}", row_num, e);
+/// The format shared by every input, from their file extensions.
+fn single_format(inputs: &[String]) -> InputFormat {
+    let format = InputFormat::from_path(&inputs[0]);
+    if let Some(other) = inputs.iter().find(|input| InputFormat::from_path(input) != format) {
+        eprintln!(
+            "❗️ Error: {} and {} have different formats; import them separately or set --format.",
+            inputs[0], other
+        );
+        std::process::exit(1);
+    }
+    format
+}
+
+/// SHA-256 of every input when checkpointing, so a resumed run can tell
+/// which inputs it already imported. Empty without `--checkpoint-file`.
+fn input_hashes(args: &Cli, inputs: &[String]) -> Result<Vec<String>> {
+    if args.checkpoint_file.is_none() {
+        return Ok(vec![String::new(); inputs.len()]);
+    }
+    if inputs.iter().any(|input| is_stdin(input)) {
+        eprintln!("❗️ Error: --checkpoint-file needs an input file; stdin cannot be checked when resuming.");
+        std::process::exit(1);
+    }
+    inputs.iter().map(|input| file_sha256(input)).collect()
+}
+
+/// Loads the checkpoint to resume from. Inputs it lists as completed are
+/// skipped, and the input in progress continues after its last row. A
+/// checkpoint that matches none of the inputs is refused.
+fn resume_checkpoint(args: &Cli, inputs: &[String], hashes: &[String]) -> Result<ResumeCheckpoint> {
+    let Some(path) = &args.checkpoint_file else {
+        return Ok(ResumeCheckpoint::default());
+    };
+    let Some(saved) = ResumeCheckpoint::load(path)? else {
+        println!("Checkpoint file not found at {}, starting from the first row", path);
+        return Ok(ResumeCheckpoint::default());
+    };
+
+    let in_progress_missing = !saved.sha256.is_empty() && !hashes.contains(&saved.sha256);
+    let nothing_matches = !hashes
+        .iter()
+        .any(|sha256| *sha256 == saved.sha256 || saved.completed.iter().any(|done| &done.sha256 == sha256));
+    if in_progress_missing || nothing_matches {
+        let checkpointed = if saved.input.is_empty() {
+            saved.completed.iter().map(|done| done.input.as_str()).collect::<Vec<_>>().join(", ")
+        } else {
+            saved.input.clone()
+        };
+        eprintln!(
+            "❗️ Error: {} does not match the file checkpointed in {} ({}); refusing to resume.",
+            inputs.join(", "),
+            path,
+            checkpointed
+        );
+        std::process::exit(1);
+    }
+    Ok(saved)
+}
+
+/// How rejected rows of an input are written: CSV rows keep the input's
+/// dialect and header, everything else is written as JSON Lines.
+enum RejectFormat {
+    Csv(CsvDialect, Option<ByteRecord>),
+    Json,
+}
+
+impl RejectFormat {
+    fn create(&self, path: &str, append: bool) -> Result<RejectWriter> {
+        match self {
+            RejectFormat::Csv(dialect, headers) => RejectWriter::create(path, dialect, headers.as_ref(), append),
+            RejectFormat::Json => RejectWriter::json(path, append),
+        }
+    }
+}
+
+fn open_csv(args: &Cli, input: &str, dialect: CsvDialect, start: &ResumeCheckpoint) -> Result<Input> {
+    match InputStream::open(input, SNIFF_BYTES, dialect.encoding()?)? {
+        InputStream::File(reader) => csv_input(args, dialect, start, reader, |rdr, position| Ok(rdr.seek(position)?)),
+        // Compressed input and stdin cannot seek, so the rows before the
+        // checkpoint are read again and dropped.
+        InputStream::Stream(reader) => csv_input(args, dialect, start, reader, |rdr, position| {
+            let mut record = ByteRecord::new();
+            while rdr.position().byte() < position.byte() {
+                if !rdr.read_byte_record(&mut record)? {
+                    return Err(anyhow!("Input ends before the checkpointed offset {}", position.byte()));
                 }
             }
-        }
+            Ok(())
+        }),
     }
+}
 
-    if !bulk_buffer.is_empty() {
-        let client = collection.client();
-        let ops: Vec<WriteModel> = bulk_buffer;
-        if let Err(e) = client.bulk_write(ops).await {
-            eprintln!("Final bulk write error: {}", e);
+fn csv_input<R: BufRead + Send + 'static>(
+    args: &Cli,
+    mut dialect: CsvDialect,
+    start: &ResumeCheckpoint,
+    mut reader: R,
+    seek: impl FnOnce(&mut Reader<R>, Position) -> Result<()>,
+) -> Result<Input> {
+    if dialect.sniff_enabled() {
+        let sniffed = sniff(reader.fill_buf()?);
+        if args.debug {
+            println!("Sniffed CSV dialect: {:?}", sniffed);
         }
+        dialect = sniffed.overridden_by(dialect);
     }
 
-    println!("✅ Completed import process.");
-    Ok(())
+    // Field counts are checked per row so that ragged rows can be rejected.
+    let flexible = dialect.flexible.unwrap_or(false);
+    let mut rdr = dialect
+        .reader_builder()?
+        .flexible(true)
+        .has_headers(!args.no_header)
+        .from_reader(reader);
+
+    let headers = if !args.no_header {
+        Some(rdr.headers()?.clone())
+    } else {
+        None
+    };
+    let expected_len = rdr.byte_headers()?.len();
+    let skip: Vec<usize> = match &headers {
+        Some(hdrs) => hdrs
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| REJECT_COLUMNS.contains(h))
+            .map(|(i, _)| i)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let byte_headers = if args.no_header { None } else { Some(rdr.byte_headers()?.clone()) };
+    let rejects = RejectFormat::Csv(dialect, byte_headers);
+
+    if start.offset > 0 {
+        let mut position = Position::new();
+        position.set_byte(start.offset).set_line(start.line);
+        seek(&mut rdr, position)?;
+    }
+
+    Ok(Input {
+        source: Box::new(CsvSource::new(rdr)),
+        columns: CsvColumns {
+            headers,
+            skip,
+            expected_len,
+            flexible,
+        },
+        rejects,
+    })
+}
+
+fn open_json(input: &str, format: InputFormat, dialect: &CsvDialect, start: &ResumeCheckpoint) -> Result<Input> {
+    let reader = InputStream::open(input, READ_BUFFER_BYTES, dialect.encoding()?)?.starting_at(start.offset)?;
+    let end = RowEnd {
+        line: start.line,
+        offset: start.offset,
+    };
+
+    Ok(Input {
+        source: Box::new(JsonSource::new(reader, format == InputFormat::JsonArray, end)),
+        columns: CsvColumns::default(),
+        rejects: RejectFormat::Json,
+    })
+}
+
+/// Parquet checkpoints count rows rather than bytes: `offset` is the number
+/// of rows read from the start of the file.
+fn open_parquet(input: &str, start: &ResumeCheckpoint) -> Result<Input> {
+    let file = open_plain_file(input, "Parquet")?;
+
+    Ok(Input {
+        source: Box::new(ParquetSource::open(file, start.offset)?),
+        columns: CsvColumns::default(),
+        rejects: RejectFormat::Json,
+    })
+}
+
+/// Fixed-width rows are cut into columns by the reader and then mapped like
+/// CSV records. Rejects are written as CSV with the layout's column names.
+fn open_fixed(
+    args: &Cli,
+    input: &str,
+    layout: &FixedLayout,
+    dialect: &CsvDialect,
+    start: &ResumeCheckpoint,
+) -> Result<Input> {
+    let names = layout.names();
+
+    let reader = InputStream::open(input, READ_BUFFER_BYTES, dialect.encoding()?)?.starting_at(start.offset)?;
+    let end = RowEnd {
+        line: start.line,
+        offset: start.offset,
+    };
+    let source = FixedSource::new(reader, layout, end).map_err(|e| anyhow!("{}: {}", args.mapping, e))?;
+
+    let headers = StringRecord::from(names.clone());
+    let rejects = RejectFormat::Csv(dialect.clone(), Some(headers.as_byte_record().clone()));
+
+    Ok(Input {
+        source: Box::new(source),
+        columns: CsvColumns {
+            headers: Some(headers),
+            skip: Vec::new(),
+            expected_len: names.len(),
+            flexible: false,
+        },
+        rejects,
+    })
+}
+
+/// Like Parquet, spreadsheet checkpoints count rows: `offset` is the number
+/// of data rows read from the sheet.
+fn open_sheet(args: &Cli, input: &str, start: &ResumeCheckpoint) -> Result<Input> {
+    open_plain_file(input, "Spreadsheet")?;
+    let selection = SheetSelection {
+        sheet: args.sheet.as_deref(),
+        range: args.cell_range.as_deref(),
+        skip_rows: args.skip_rows.unwrap_or(0),
+        headers: !args.no_header,
+    };
+    Ok(Input {
+        source: Box::new(SheetSource::open(input, &selection, start.offset)?),
+        columns: CsvColumns::default(),
+        rejects: RejectFormat::Json,
+    })
+}
+
+/// Asks the server whether it is recent enough for client-level `bulk_write`.
+async fn server_supports_bulk_write(collection: &Collection<Document>) -> Result<bool> {
+    let supported = is_mongo_8_or_higher(collection.client()).await?;
+    if !supported {
+        println!("MongoDB server is older than 8.0, using insert_many/update_one for batches");
+    }
+    Ok(supported)
+}
+
+/// Prints mapping issues and exits when any of them is fatal. With `--strict`
+/// warnings are fatal too.
+fn report_mapping_issues(mapping_path: &str, issues: Vec<Issue>, strict: bool) {
+    let mut fatal = false;
+    for issue in &issues {
+        eprintln!("{}: {}", mapping_path, issue);
+        fatal |= issue.severity == Severity::Error || strict;
+    }
+    if fatal {
+        eprintln!("❗️ Error: Mapping file {} failed validation.", mapping_path);
+        std::process::exit(1);
+    }
 }
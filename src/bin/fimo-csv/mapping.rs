@@ -1,10 +1,28 @@
-use serde::Deserialize;
+use crate::dialect::CsvDialect;
+use crate::fixed::FixedLayout;
+use crate::path::{parse_path, PathSegment};
+use anyhow::{anyhow, Result};
+use bson::DateTime;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
+use std::sync::OnceLock;
 
 #[derive(Debug, Deserialize)]
 pub struct FieldMapping(pub HashMap<String, FieldDef>);
 
+/// Every value accepted for `type`.
+pub const FIELD_TYPES: [&str; 11] = [
+    "string", "int", "long", "double", "decimal", "bool", "objectId", "date", "timestamp", "regex", "array",
+];
+
+/// Every value accepted for `element_type` of an `array` field.
+pub const ELEMENT_TYPES: [&str; 10] = [
+    "string", "int", "long", "double", "decimal", "bool", "objectId", "date", "timestamp", "regex",
+];
+
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct FieldDef {
     pub r#type: String,
     #[serde(default)]
@@ -15,9 +33,218 @@ pub struct FieldDef {
     pub falsy: Option<Vec<String>>,
     #[serde(default)]
     pub formats: Option<Vec<String>>, // Date format patterns, used for "date" type fields
+    #[serde(default, alias = "path")]
+    pub target: Option<String>, // Destination path, e.g. "address.city" or "phones[0].number"
+    #[serde(default)]
+    pub separator: Option<String>, // Splits "array" cells, defaults to ","
+    #[serde(default)]
+    pub element_type: Option<String>, // Type of each "array" element, defaults to "string"
+    #[serde(default)]
+    pub trim: bool, // Trim whitespace around each "array" element
+    #[serde(default)]
+    pub unique: bool, // Drop repeated "array" elements
+    #[serde(default)]
+    pub null_values: Option<Vec<String>>, // Cell values treated as empty, defaults to [""]
+    #[serde(default, deserialize_with = "deserialize_on_empty")]
+    pub on_empty: Option<OnEmpty>,
+    #[serde(default)]
+    pub default: Option<DefaultValue>,
+    #[serde(default)]
+    pub r#enum: Option<Vec<serde_yaml::Value>>, // Allowed raw values
+    #[serde(default)]
+    pub pattern: Option<String>, // Regex the raw value must match
+    #[serde(default)]
+    pub min: Option<serde_yaml::Value>, // Lower bound for numeric and date fields
+    #[serde(default)]
+    pub max: Option<serde_yaml::Value>, // Upper bound for numeric and date fields
+    #[serde(default)]
+    pub min_length: Option<usize>, // Minimum length in characters for string fields
+    #[serde(default)]
+    pub max_length: Option<usize>, // Maximum length in characters for string fields
+    #[serde(skip)]
+    pub compiled_pattern: OnceLock<Regex>,
+    #[serde(skip)]
+    pub parsed_target: OnceLock<Vec<PathSegment>>,
+    #[serde(skip)]
+    pub date_bounds: OnceLock<(Option<DateTime>, Option<DateTime>)>, // `min` and `max` of a date field
+}
+
+/// What to store when a cell matches one of the field's `null_values`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnEmpty {
+    /// Convert the value like any other (the behaviour without `on_empty`).
+    Keep,
+    /// Leave the key out of the document.
+    Omit,
+    /// Store BSON null.
+    Null,
+    /// Store the field's `default`.
+    Default,
+}
+
+/// A field default: either a literal converted with the field's type, or a
+/// value generated per row (`now`, `objectId` or `uuid`).
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum DefaultValue {
+    Generate { generate: String },
+    Literal(serde_yaml::Value),
+}
+
+// `on_empty: null` is read by YAML as a null value rather than the string "null".
+fn deserialize_on_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<OnEmpty>, D::Error> {
+    Ok(Some(Option::<OnEmpty>::deserialize(deserializer)?.unwrap_or(OnEmpty::Null)))
+}
+
+/// Text of a scalar YAML value as it would appear in a CSV cell. Returns
+/// `None` for null, sequences and mappings.
+pub fn scalar_text(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(s) => Some(s.clone()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        serde_yaml::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+impl FieldDef {
+    pub fn is_null_value(&self, value: &str) -> bool {
+        match &self.null_values {
+            Some(list) => list.iter().any(|n| n == value),
+            None => value.is_empty(),
+        }
+    }
+
+    /// The compiled `pattern`, built on first use.
+    pub fn pattern_regex(&self) -> Option<Result<&Regex>> {
+        let pattern = self.pattern.as_ref()?;
+        if let Some(regex) = self.compiled_pattern.get() {
+            return Some(Ok(regex));
+        }
+        Some(
+            Regex::new(pattern)
+                .map(|regex| self.compiled_pattern.get_or_init(|| regex))
+                .map_err(|e| anyhow!("Invalid pattern '{}': {}", pattern, e)),
+        )
+    }
+
+    /// The segments of `target`, parsed on first use.
+    pub fn target_path(&self) -> Option<Result<&[PathSegment]>> {
+        let target = self.target.as_ref()?;
+        if let Some(segments) = self.parsed_target.get() {
+            return Some(Ok(segments));
+        }
+        Some(parse_path(target).map(|segments| self.parsed_target.get_or_init(|| segments).as_slice()))
+    }
+
+    /// The configured `on_empty` policy. A field with a `default` but no
+    /// explicit policy uses the default.
+    pub fn on_empty(&self) -> OnEmpty {
+        match (self.on_empty, &self.default) {
+            (Some(policy), _) => policy,
+            (None, Some(_)) => OnEmpty::Default,
+            (None, None) => OnEmpty::Keep,
+        }
+    }
+}
+
+/// A mapping file. Either a flat map of field definitions, or a sectioned
+/// file with the field definitions under `fields:` and optional settings
+/// such as the CSV dialect or a fixed-width layout alongside them.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MappingFile {
+    #[serde(default)]
+    pub csv: CsvDialect,
+    #[serde(default)]
+    pub fixed: Option<FixedLayout>,
+    #[serde(default)]
+    pub indexes: Vec<IndexDef>,
+    #[serde(default)]
+    pub op_column: Option<String>, // Field naming the operation of each row
+    #[serde(default)]
+    pub op_expr: Option<String>, // Template rendering the operation of each row
+    #[serde(default)]
+    pub op_map: HashMap<String, String>, // Routed values to operation or template names, e.g. I: insert
+    pub fields: FieldMapping,
+}
+
+/// An index the target collection should have, created before loading.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IndexDef {
+    pub keys: serde_yaml::Mapping, // Field to 1, -1, "text", "2dsphere" or "hashed", in index order
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub unique: bool,
+    #[serde(default)]
+    pub sparse: bool,
+    #[serde(default)]
+    pub expire_after_seconds: Option<u64>, // Makes a TTL index on a single date field
+    #[serde(default)]
+    pub partial_filter: Option<serde_yaml::Value>, // Only index documents matching this filter
+}
+
+/// Top-level keys of a sectioned mapping file.
+const SECTIONS: [&str; 7] = ["csv", "fixed", "indexes", "op_column", "op_expr", "op_map", "fields"];
+
+pub fn load_mapping(text: &str) -> Result<MappingFile> {
+    let value: serde_yaml::Value = serde_yaml::from_str(text)?;
+    // A flat file may have columns called `fields` or `csv`, but their
+    // definitions hold scalars such as `type`, never only field definitions.
+    let sectioned = value.as_mapping().is_some_and(|top| {
+        top.keys().all(|key| key.as_str().is_some_and(|key| SECTIONS.contains(&key)))
+            && top
+                .get("fields")
+                .and_then(|fields| fields.as_mapping())
+                .is_some_and(|fields| fields.values().all(|def| def.is_mapping()))
+    });
+
+    if sectioned {
+        Ok(serde_yaml::from_str(text)?)
+    } else {
+        Ok(MappingFile {
+            csv: CsvDialect::default(),
+            fixed: None,
+            indexes: Vec::new(),
+            op_column: None,
+            op_expr: None,
+            op_map: HashMap::new(),
+            fields: serde_yaml::from_str(text)?,
+        })
+    }
 }
 
 pub fn requires_extended_json(mapping: &FieldMapping) -> bool {
     const BSON_TYPES: [&str; 6] = ["objectId", "date", "decimal", "regex", "timestamp", "binary"];
-    mapping.0.values().any(|f| BSON_TYPES.contains(&f.r#type.as_str()))
+    mapping.0.values().any(|f| {
+        BSON_TYPES.contains(&f.r#type.as_str())
+            || f.element_type.as_deref().is_some_and(|t| BSON_TYPES.contains(&t))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_mapping_may_have_columns_named_like_sections() {
+        let mapping = load_mapping("fields:\n  type: string\ncsv:\n  type: int\n  required: true\n").unwrap();
+        assert_eq!(mapping.fields.0["fields"].r#type, "string");
+        assert_eq!(mapping.fields.0["csv"].r#type, "int");
+        assert!(mapping.op_column.is_none());
+    }
+
+    #[test]
+    fn sectioned_mapping_may_have_a_field_named_type() {
+        let mapping = load_mapping(
+            "csv:\n  delimiter: \";\"\nop_column: op\nfields:\n  type:\n    type: string\n  op:\n    type: string\n",
+        )
+        .unwrap();
+        assert_eq!(mapping.fields.0["type"].r#type, "string");
+        assert_eq!(mapping.csv.delimiter.as_deref(), Some(";"));
+        assert_eq!(mapping.op_column.as_deref(), Some("op"));
+    }
 }
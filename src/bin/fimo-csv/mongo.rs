@@ -1,12 +1,549 @@
 // src/mongodb.rs
-use mongodb::{Client, Collection};
-use mongodb::options::ClientOptions;
-use bson::Document;
-use anyhow::Result;
+use crate::mapping::IndexDef;
+use crate::reject::{bulk_failures, write_concern_errors};
+use crate::report::WriteCounts;
+use mongodb::{Client, Collection, IndexModel, Namespace};
+use mongodb::error::{Error, ErrorKind};
+use mongodb::options::{
+    ClientOptions, Collation, DeleteOptions, Hint, IndexOptions, ReplaceOptions, UpdateOptions, WriteModel,
+};
+use mongodb::results::{DeleteResult, UpdateResult};
+use bson::{Bson, Document};
+use anyhow::{anyhow, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
 
 pub async fn connect(uri: &str, db: &str, collection: &str) -> Result<Collection<Document>> {
     let client_options = ClientOptions::parse(uri).await?;
     let client = Client::with_options(client_options)?;
     let db = client.database(db);
     Ok(db.collection::<Document>(collection))
-}
\ No newline at end of file
+}
+
+/// A batch that was not fully written. `failed` holds the index in the batch
+/// and the error message of every write that was not applied.
+pub struct BatchFailure {
+    pub message: String,
+    pub failed: Vec<(usize, String)>,
+}
+
+/// Creates the indexes declared in the mapping file, returning their names.
+/// Indexes that already exist with the same definition are left as they are.
+pub async fn create_indexes(collection: &Collection<Document>, indexes: &[IndexDef]) -> Result<Vec<String>> {
+    if indexes.is_empty() {
+        return Ok(Vec::new());
+    }
+    let models = indexes.iter().map(index_model).collect::<Result<Vec<_>>>()?;
+    Ok(collection.create_indexes(models).await?.index_names)
+}
+
+/// Builds the index model of a declaration, checking its keys and options.
+pub fn index_model(index: &IndexDef) -> Result<IndexModel> {
+    let mut keys = Document::new();
+    for (field, direction) in &index.keys {
+        let field = field.as_str().ok_or_else(|| anyhow!("Index keys must be field names"))?;
+        let direction = match (direction.as_i64(), direction.as_str()) {
+            (Some(order @ (1 | -1)), _) => Bson::Int32(order as i32),
+            (None, Some(kind)) => Bson::String(kind.to_string()),
+            _ => return Err(anyhow!("Index key '{}' must be 1, -1 or an index type such as \"text\"", field)),
+        };
+        keys.insert(field, direction);
+    }
+    if keys.is_empty() {
+        return Err(anyhow!("An index needs at least one key"));
+    }
+    if index.expire_after_seconds.is_some() && keys.len() > 1 {
+        return Err(anyhow!("A TTL index (expire_after_seconds) must have a single date key"));
+    }
+    let partial_filter = match &index.partial_filter {
+        Some(filter) => match Bson::try_from(serde_json::to_value(filter)?)? {
+            Bson::Document(filter) => Some(filter),
+            _ => return Err(anyhow!("partial_filter must be a document")),
+        },
+        None => None,
+    };
+
+    let options = IndexOptions::builder()
+        .name(index.name.clone())
+        .unique(index.unique.then_some(true))
+        .sparse(index.sparse.then_some(true))
+        .expire_after(index.expire_after_seconds.map(Duration::from_secs))
+        .partial_filter_expression(partial_filter)
+        .build();
+    Ok(IndexModel::builder().keys(keys).options(options).build())
+}
+
+/// A write model and the `let` variables of its template. The driver only
+/// takes `let` per call or for a whole bulk write, not per model.
+pub struct RowWrite {
+    pub model: WriteModel,
+    pub let_vars: Option<Document>,
+}
+
+/// The result of writing one batch: what was written and what was not.
+pub struct BatchOutcome {
+    pub counts: WriteCounts,
+    pub failure: Option<BatchFailure>,
+    /// Write concern errors of writes that were applied, also counted in `counts`.
+    pub write_concern_errors: Vec<String>,
+}
+
+/// Writes batches of models. Cheap to clone, so every in-flight batch gets
+/// its own copy.
+#[derive(Clone)]
+pub struct BatchWriter {
+    pub client: Client,
+    /// Use client-level `bulk_write` (MongoDB 8+).
+    pub bulk_write: bool,
+    /// Write every model with its own `insert_one`/`update_one` call
+    /// instead of batching (`--batch-size 0`).
+    pub single: bool,
+    /// Stop at the first failed write and keep writes in batch order.
+    pub ordered: bool,
+    /// Parallel single-document calls per unordered batch, before MongoDB
+    /// 8.0 and in single-write mode.
+    pub concurrency: usize,
+}
+
+impl BatchWriter {
+    /// Writes a batch of models. On MongoDB 8+ this is a single `bulk_write`;
+    /// older servers, and batches with per-row `let` variables, get
+    /// `insert_many` for runs of inserts and single-document calls for the
+    /// other writes: one at a time when ordered, otherwise up to
+    /// `concurrency` in parallel.
+    pub async fn write(&self, writes: Vec<RowWrite>) -> BatchOutcome {
+        let batch_len = writes.len();
+        let mut counts = WriteCounts::default();
+
+        if self.single {
+            let writes = writes.into_iter().enumerate().collect();
+            let results = if self.ordered {
+                write_in_order(writes, |write| write_one(&self.client, write)).await
+            } else {
+                write_concurrently(&self.client, writes, self.concurrency).await
+            };
+            let mut failed = Vec::new();
+            for (index, result) in results {
+                match result {
+                    Ok(written) => written.count(&mut counts),
+                    Err(message) => failed.push((index, message)),
+                }
+            }
+            return outcome(counts, failed, Vec::new(), batch_len);
+        }
+
+        if self.bulk_write && writes.iter().all(|write| write.let_vars.is_none()) {
+            let models: Vec<WriteModel> = writes.into_iter().map(|write| write.model).collect();
+            let (failure, concern) = match self.client.bulk_write(models).ordered(self.ordered).await {
+                Ok(result) => {
+                    counts.add_bulk(&result);
+                    (None, Vec::new())
+                }
+                Err(e) => {
+                    counts.add_bulk_error(&e);
+                    let concern = write_concern_errors(&e);
+                    let failed = bulk_failures(&e, batch_len, self.ordered);
+                    // A write concern error alone leaves every write applied.
+                    let failure = (concern.is_empty() || !failed.is_empty()).then(|| BatchFailure {
+                        message: e.to_string(),
+                        failed,
+                    });
+                    (failure, concern)
+                }
+            };
+            counts.write_concern_errors += concern.len() as i64;
+            return BatchOutcome {
+                counts,
+                failure,
+                write_concern_errors: concern,
+            };
+        }
+
+        let mut failed: Vec<(usize, String)> = Vec::new();
+        let mut concern: Vec<String> = Vec::new();
+        let mut models = writes.into_iter().enumerate().peekable();
+
+        while let Some((index, write)) = models.next() {
+            if let WriteModel::InsertOne(first) = write.model {
+                let namespace = first.namespace;
+                let mut indexes = vec![index];
+                let mut docs = vec![first.document];
+                while let Some((next_index, RowWrite { model: WriteModel::InsertOne(next), .. })) = models.peek() {
+                    if next.namespace != namespace {
+                        break;
+                    }
+                    indexes.push(*next_index);
+                    docs.push(next.document.clone());
+                    models.next();
+                }
+
+                let inserted = docs.len();
+                let result = collection_for(&self.client, &namespace)
+                    .insert_many(docs)
+                    .ordered(self.ordered)
+                    .await;
+                match result {
+                    Ok(_) => counts.inserted += inserted as i64,
+                    Err(e) => {
+                        let run_concern = write_concern_errors(&e);
+                        counts.write_concern_errors += run_concern.len() as i64;
+                        concern.extend(run_concern);
+                        let run_failures = insert_many_failures(&e, indexes.len(), self.ordered);
+                        counts.inserted += (inserted - run_failures.len()) as i64;
+                        let Some(&(first_failed, _)) = run_failures.first() else {
+                            continue;
+                        };
+                        let failed_at = indexes[first_failed];
+                        failed.extend(run_failures.into_iter().map(|(i, message)| (indexes[i], message)));
+                        if self.ordered {
+                            // Like an ordered bulk write, nothing after the first failure is attempted.
+                            failed.extend(models.by_ref().map(|(i, _)| {
+                                (i, format!("Not attempted: write {} of the ordered batch failed", failed_at + 1))
+                            }));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let mut run = vec![(index, write)];
+            while let Some((_, next)) = models.peek() {
+                if matches!(next.model, WriteModel::InsertOne(_)) {
+                    break;
+                }
+                run.extend(models.next());
+            }
+            let results = if self.ordered {
+                write_in_order(run, |write| write_one(&self.client, write)).await
+            } else {
+                write_concurrently(&self.client, run, self.concurrency).await
+            };
+            let mut failed_at = None;
+            for (index, result) in results {
+                match result {
+                    Ok(written) => written.count(&mut counts),
+                    Err(message) => {
+                        failed_at = failed_at.or(Some(index));
+                        failed.push((index, message));
+                    }
+                }
+            }
+            if let (true, Some(failed_at)) = (self.ordered, failed_at) {
+                failed.extend(models.by_ref().map(|(i, _)| {
+                    (i, format!("Not attempted: write {} of the ordered batch failed", failed_at + 1))
+                }));
+            }
+        }
+
+        outcome(counts, failed, concern, batch_len)
+    }
+}
+
+fn outcome(
+    counts: WriteCounts,
+    mut failed: Vec<(usize, String)>,
+    write_concern_errors: Vec<String>,
+    batch_len: usize,
+) -> BatchOutcome {
+    if failed.is_empty() {
+        return BatchOutcome {
+            counts,
+            failure: None,
+            write_concern_errors,
+        };
+    }
+    failed.sort_by_key(|(index, _)| *index);
+    let message = if batch_len == 1 {
+        failed[0].1.clone()
+    } else {
+        format!("{} of {} writes failed, first: {}", failed.len(), batch_len, failed[0].1)
+    };
+    BatchOutcome {
+        counts,
+        failure: Some(BatchFailure { message, failed }),
+        write_concern_errors,
+    }
+}
+
+/// The result of a single write outside of `bulk_write`.
+enum Written {
+    Inserted,
+    Updated(UpdateResult),
+    Deleted(DeleteResult),
+}
+
+impl Written {
+    fn count(&self, counts: &mut WriteCounts) {
+        match self {
+            Written::Inserted => counts.add_insert(),
+            Written::Updated(result) => counts.add_update(result),
+            Written::Deleted(result) => counts.add_delete(result),
+        }
+    }
+}
+
+async fn write_one(client: &Client, write: RowWrite) -> std::result::Result<Written, String> {
+    let let_vars = write.let_vars;
+    match write.model {
+        WriteModel::InsertOne(insert) => collection_for(client, &insert.namespace)
+            .insert_one(insert.document)
+            .await
+            .map(|_| Written::Inserted)
+            .map_err(|e| e.to_string()),
+        WriteModel::UpdateOne(update) => {
+            let options = UpdateOptions::builder()
+                .upsert(update.upsert)
+                .array_filters(array_filters(update.array_filters))
+                .collation(collation(update.collation)?)
+                .hint(hint(update.hint))
+                .sort(update.sort)
+                .let_vars(let_vars)
+                .build();
+            collection_for(client, &update.namespace)
+                .update_one(update.filter, update.update)
+                .with_options(options)
+                .await
+                .map(Written::Updated)
+                .map_err(|e| e.to_string())
+        }
+        WriteModel::UpdateMany(update) => {
+            let options = UpdateOptions::builder()
+                .upsert(update.upsert)
+                .array_filters(array_filters(update.array_filters))
+                .collation(collation(update.collation)?)
+                .hint(hint(update.hint))
+                .let_vars(let_vars)
+                .build();
+            collection_for(client, &update.namespace)
+                .update_many(update.filter, update.update)
+                .with_options(options)
+                .await
+                .map(Written::Updated)
+                .map_err(|e| e.to_string())
+        }
+        WriteModel::ReplaceOne(replace) => {
+            let options = ReplaceOptions::builder()
+                .upsert(replace.upsert)
+                .collation(collation(replace.collation)?)
+                .hint(hint(replace.hint))
+                .sort(replace.sort)
+                .let_vars(let_vars)
+                .build();
+            collection_for(client, &replace.namespace)
+                .replace_one(replace.filter, replace.replacement)
+                .with_options(options)
+                .await
+                .map(Written::Updated)
+                .map_err(|e| e.to_string())
+        }
+        WriteModel::DeleteOne(delete) => {
+            let options = DeleteOptions::builder()
+                .collation(collation(delete.collation)?)
+                .hint(hint(delete.hint))
+                .let_vars(let_vars)
+                .build();
+            collection_for(client, &delete.namespace)
+                .delete_one(delete.filter)
+                .with_options(options)
+                .await
+                .map(Written::Deleted)
+                .map_err(|e| e.to_string())
+        }
+        WriteModel::DeleteMany(delete) => {
+            let options = DeleteOptions::builder()
+                .collation(collation(delete.collation)?)
+                .hint(hint(delete.hint))
+                .let_vars(let_vars)
+                .build();
+            collection_for(client, &delete.namespace)
+                .delete_many(delete.filter)
+                .with_options(options)
+                .await
+                .map(Written::Deleted)
+                .map_err(|e| e.to_string())
+        }
+        _ => Err("Write model is not supported before MongoDB 8.0".to_string()),
+    }
+}
+
+// Write models hold their options as plain BSON; single-document calls
+// take them typed.
+
+fn array_filters(filters: Option<Vec<Bson>>) -> Option<Vec<Document>> {
+    filters.map(|filters| {
+        filters
+            .into_iter()
+            .filter_map(|filter| match filter {
+                Bson::Document(filter) => Some(filter),
+                _ => None,
+            })
+            .collect()
+    })
+}
+
+fn collation(collation: Option<Document>) -> std::result::Result<Option<Collation>, String> {
+    collation
+        .map(bson::from_document::<Collation>)
+        .transpose()
+        .map_err(|e| format!("Invalid collation: {}", e))
+}
+
+fn hint(hint: Option<Bson>) -> Option<Hint> {
+    match hint {
+        Some(Bson::String(name)) => Some(Hint::Name(name)),
+        Some(Bson::Document(keys)) => Some(Hint::Keys(keys)),
+        _ => None,
+    }
+}
+
+fn collection_for(client: &Client, namespace: &Namespace) -> Collection<Document> {
+    client.database(&namespace.db).collection::<Document>(&namespace.coll)
+}
+
+/// Maps an `insert_many` error to the documents that were not inserted: the
+/// failed ones and, when ordered, every one after the first failure.
+fn insert_many_failures(error: &Error, run_len: usize, ordered: bool) -> Vec<(usize, String)> {
+    match &*error.kind {
+        ErrorKind::InsertMany(insert) => match &insert.write_errors {
+            Some(write_errors) if !ordered => write_errors
+                .iter()
+                .map(|write_error| (write_error.index, write_error.message.clone()))
+                .collect(),
+            Some(write_errors) if !write_errors.is_empty() => {
+                let first = &write_errors[0];
+                let mut failures = vec![(first.index, first.message.clone())];
+                failures.extend(((first.index + 1)..run_len).map(|i| {
+                    (i, format!("Not attempted: write {} of the ordered batch failed", first.index + 1))
+                }));
+                failures
+            }
+            // Only the write concern failed; the documents themselves were written
+            // and the error is reported by `write_concern_errors`.
+            _ => Vec::new(),
+        },
+        _ => (0..run_len).map(|i| (i, error.to_string())).collect(),
+    }
+}
+
+/// Writes a run one model at a time. Like an ordered bulk write, nothing
+/// after the first failure is attempted.
+async fn write_in_order<W, F, Fut>(run: Vec<(usize, W)>, mut write: F) -> Vec<(usize, std::result::Result<Written, String>)>
+where
+    F: FnMut(W) -> Fut,
+    Fut: Future<Output = std::result::Result<Written, String>>,
+{
+    let mut results = Vec::with_capacity(run.len());
+    let mut failed_at = None;
+    for (index, model) in run {
+        let result = match failed_at {
+            Some(failed_at) => Err(format!("Not attempted: write {} of the ordered batch failed", failed_at + 1)),
+            None => write(model).await,
+        };
+        if result.is_err() && failed_at.is_none() {
+            failed_at = Some(index);
+        }
+        results.push((index, result));
+    }
+    results
+}
+
+async fn write_concurrently(
+    client: &Client,
+    run: Vec<(usize, RowWrite)>,
+    concurrency: usize,
+) -> Vec<(usize, std::result::Result<Written, String>)> {
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = FuturesUnordered::new();
+
+    for (index, write) in run {
+        let permit = semaphore.clone().acquire_owned().await.unwrap();
+        let client = client.clone();
+
+        let task = tokio::spawn(async move {
+            let _permit = permit;
+            write_one(&client, write).await
+        });
+        // A task that panicked lost its write, which then fails its row.
+        tasks.push(async move { (index, task.await.unwrap_or_else(|e| Err(format!("Write task failed: {}", e)))) });
+    }
+
+    let mut results = Vec::new();
+    while let Some(result) = tasks.next().await {
+        results.push(result);
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::cell::RefCell;
+
+    #[test]
+    fn ordered_runs_write_one_at_a_time_and_stop_at_the_first_failure() {
+        let log = RefCell::new(Vec::new());
+        let started = &log;
+        let run = vec![(0, "upsert"), (1, "fail"), (2, "delete"), (3, "upsert")];
+
+        let results = block_on(write_in_order(run, |op| {
+            started.borrow_mut().push(op);
+            async move {
+                // Each write finishes before the next one starts.
+                assert_eq!(started.borrow().last(), Some(&op));
+                match op {
+                    "fail" => Err("E11000 duplicate key".to_string()),
+                    _ => Ok(Written::Inserted),
+                }
+            }
+        }));
+
+        assert_eq!(*log.borrow(), vec!["upsert", "fail"]);
+        let outcomes: Vec<_> = results.iter().map(|(i, r)| (*i, r.as_ref().err().cloned())).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (0, None),
+                (1, Some("E11000 duplicate key".to_string())),
+                (2, Some("Not attempted: write 2 of the ordered batch failed".to_string())),
+                (3, Some("Not attempted: write 2 of the ordered batch failed".to_string())),
+            ]
+        );
+    }
+
+    fn index(yaml: &str) -> Result<IndexModel> {
+        index_model(&serde_yaml::from_str::<IndexDef>(yaml).unwrap())
+    }
+
+    #[test]
+    fn compound_indexes_keep_their_key_order() {
+        let model = index("keys: { last: 1, first: -1, bio: text }\nname: people\nunique: true").unwrap();
+        assert_eq!(model.keys, bson::doc! { "last": 1, "first": -1, "bio": "text" });
+        let options = model.options.unwrap();
+        assert_eq!(options.name.as_deref(), Some("people"));
+        assert_eq!(options.unique, Some(true));
+        assert_eq!(options.sparse, None);
+    }
+
+    #[test]
+    fn ttl_indexes_need_a_single_key() {
+        let model = index("keys: { seen: 1 }\nexpire_after_seconds: 3600").unwrap();
+        assert_eq!(model.options.unwrap().expire_after, Some(Duration::from_secs(3600)));
+        assert!(index("keys: { seen: 1, user: 1 }\nexpire_after_seconds: 3600").is_err());
+    }
+
+    #[test]
+    fn index_declarations_are_checked() {
+        assert!(index("keys: {}").is_err());
+        assert!(index("keys: { a: 2 }").is_err());
+        assert!(index("keys: { a: 1 }\npartial_filter: [1]").is_err());
+        let model = index("keys: { a: 1 }\npartial_filter: { a: { $exists: true } }").unwrap();
+        assert_eq!(
+            model.options.unwrap().partial_filter_expression,
+            Some(bson::doc! { "a": { "$exists": true } })
+        );
+    }
+}
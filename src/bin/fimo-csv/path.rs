@@ -0,0 +1,200 @@
+// src/path.rs
+use anyhow::{anyhow, Result};
+use bson::{Bson, Document};
+
+/// One step of a target path such as `address.city` or `phones[0].number`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Parses a dotted target path. Keys are separated by `.`, array positions
+/// are written as `[n]` directly after a key, e.g. `items[2].sku`.
+pub fn parse_path(path: &str) -> Result<Vec<PathSegment>> {
+    let mut segments = Vec::new();
+
+    for part in path.split('.') {
+        let (name, mut indexes) = match part.find('[') {
+            Some(pos) => (&part[..pos], &part[pos..]),
+            None => (part, ""),
+        };
+
+        if name.is_empty() {
+            return Err(anyhow!("Invalid target path '{}': empty key", path));
+        }
+        segments.push(PathSegment::Key(name.to_string()));
+
+        while !indexes.is_empty() {
+            let close = indexes
+                .find(']')
+                .filter(|_| indexes.starts_with('['))
+                .ok_or_else(|| anyhow!("Invalid target path '{}': malformed array index", path))?;
+            let index = indexes[1..close]
+                .parse::<usize>()
+                .map_err(|_| anyhow!("Invalid target path '{}': '{}' is not an array index", path, &indexes[1..close]))?;
+            segments.push(PathSegment::Index(index));
+            indexes = &indexes[close + 1..];
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Inserts `value` into `doc` at `segments`, creating intermediate
+/// sub-documents and arrays as needed. Array gaps are filled with `GAP`, so
+/// a later field may still take them; call `fill_gaps` once the document is
+/// complete.
+pub fn insert_path(doc: &mut Document, segments: &[PathSegment], value: Bson) -> Result<()> {
+    let (first, rest) = segments
+        .split_first()
+        .ok_or_else(|| anyhow!("Target path is empty"))?;
+
+    let key = match first {
+        PathSegment::Key(key) => key,
+        PathSegment::Index(_) => return Err(anyhow!("Target path must start with a key")),
+    };
+
+    match rest.first() {
+        None if doc.contains_key(key) => Err(anyhow!("another field is already mapped there")),
+        None => {
+            doc.insert(key.clone(), value);
+            Ok(())
+        }
+        Some(next) => {
+            let slot = doc.entry(key.clone()).or_insert_with(|| empty_container(next));
+            insert_nested(slot, rest, value)
+        }
+    }
+}
+
+fn insert_nested(slot: &mut Bson, segments: &[PathSegment], value: Bson) -> Result<()> {
+    match (slot, &segments[0]) {
+        (Bson::Document(doc), PathSegment::Key(_)) => insert_path(doc, segments, value),
+        (Bson::Array(items), PathSegment::Index(index)) => {
+            let index = *index;
+            if items.len() <= index {
+                items.resize(index + 1, GAP);
+            }
+            match segments.get(1) {
+                None if items[index] != GAP => {
+                    Err(anyhow!("another field is already mapped there"))
+                }
+                None => {
+                    items[index] = value;
+                    Ok(())
+                }
+                Some(next) => {
+                    if items[index] == GAP {
+                        items[index] = empty_container(next);
+                    }
+                    insert_nested(&mut items[index], &segments[1..], value)
+                }
+            }
+        }
+        (_, PathSegment::Key(key)) => Err(anyhow!("key '{}' needs a sub-document but another field put a different value there", key)),
+        (_, PathSegment::Index(index)) => Err(anyhow!("index [{}] needs an array but another field put a different value there", index)),
+    }
+}
+
+/// Placeholder for array positions no field has filled yet. An explicit
+/// null is a value like any other and is not overwritten.
+const GAP: Bson = Bson::Undefined;
+
+/// Turns the array positions no field filled into null.
+pub fn fill_gaps(doc: &mut Document) {
+    for (_, value) in doc.iter_mut() {
+        fill_gaps_in(value);
+    }
+}
+
+fn fill_gaps_in(value: &mut Bson) {
+    match value {
+        Bson::Document(doc) => fill_gaps(doc),
+        Bson::Array(items) => items.iter_mut().for_each(fill_gaps_in),
+        gap if *gap == GAP => *gap = Bson::Null,
+        _ => {}
+    }
+}
+
+fn empty_container(next: &PathSegment) -> Bson {
+    match next {
+        PathSegment::Key(_) => Bson::Document(Document::new()),
+        PathSegment::Index(_) => Bson::Array(Vec::new()),
+    }
+}
+
+/// Returns the value at `segments`, if there is one.
+pub fn get_path<'a>(doc: &'a Document, segments: &[PathSegment]) -> Option<&'a Bson> {
+    let (PathSegment::Key(key), rest) = segments.split_first()? else {
+        return None;
+    };
+    let mut value = doc.get(key)?;
+    for segment in rest {
+        value = match (value, segment) {
+            (Bson::Document(doc), PathSegment::Key(key)) => doc.get(key)?,
+            (Bson::Array(items), PathSegment::Index(index)) => items.get(*index)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// Removes and returns the value at `segments`. Array elements are replaced
+/// with a gap so later positions keep their index; sub-documents left empty
+/// are removed.
+pub fn take_path(doc: &mut Document, segments: &[PathSegment]) -> Option<Bson> {
+    let (PathSegment::Key(key), rest) = segments.split_first()? else {
+        return None;
+    };
+    if rest.is_empty() {
+        return doc.remove(key);
+    }
+    let taken = take_nested(doc.get_mut(key)?, rest)?;
+    if matches!(doc.get(key), Some(Bson::Document(sub)) if sub.is_empty()) {
+        doc.remove(key);
+    }
+    Some(taken)
+}
+
+fn take_nested(slot: &mut Bson, segments: &[PathSegment]) -> Option<Bson> {
+    match (slot, &segments[0]) {
+        (Bson::Document(doc), PathSegment::Key(_)) => take_path(doc, segments),
+        (Bson::Array(items), PathSegment::Index(index)) => {
+            let item = items.get_mut(*index)?;
+            if segments.len() == 1 {
+                Some(std::mem::replace(item, GAP))
+            } else {
+                take_nested(item, &segments[1..])
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn insert(doc: &mut Document, path: &str, value: Bson) -> Result<()> {
+        insert_path(doc, &parse_path(path)?, value)
+    }
+
+    #[test]
+    fn later_fields_fill_the_gaps_of_earlier_ones() {
+        let mut doc = Document::new();
+        insert(&mut doc, "phones[2].number", Bson::from("3")).unwrap();
+        insert(&mut doc, "phones[0].number", Bson::from("1")).unwrap();
+        fill_gaps(&mut doc);
+        assert_eq!(doc, doc! { "phones": [{ "number": "1" }, null, { "number": "3" }] });
+    }
+
+    #[test]
+    fn an_explicit_null_is_a_conflict() {
+        let mut doc = Document::new();
+        insert(&mut doc, "tags[0]", Bson::Null).unwrap();
+        assert!(insert(&mut doc, "tags[0]", Bson::from("a")).is_err());
+        assert!(insert(&mut doc, "tags[0].name", Bson::from("a")).is_err());
+    }
+}
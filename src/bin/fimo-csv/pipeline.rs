@@ -0,0 +1,912 @@
+// src/pipeline.rs
+//
+// The import runs as a pipeline: a reader thread parses CSV records into
+// chunks, a pool of worker threads maps and renders them, and the async
+// coordinator batches the rendered documents and keeps up to
+// `--max-inflight-batches` batches writing at once. Every stage is joined by
+// a bounded channel, so a slow server holds back the workers and the reader
+// instead of buffering the whole file in memory.
+use crate::checkpoint::{CheckpointTracker, RowEnd};
+use crate::mapping::FieldMapping;
+use crate::mongo::{BatchOutcome, BatchWriter, RowWrite};
+use crate::input::{RawRecord, RecordSource};
+use crate::reject::{strip_row_prefix, RejectWriter, Stage, REJECT_COLUMNS};
+use crate::report::ImportSummary;
+use crate::transform::{
+    apply_mapping, apply_mapping_document, render_operation, validate_required_document, validate_required_fields,
+    Operation, RowOrigin, OPERATIONS,
+};
+
+use anyhow::{anyhow, Result};
+use bson::{Bson, Document};
+use csv::{ByteRecord, StringRecord};
+use futures::stream::{FuturesUnordered, StreamExt};
+use minijinja::Environment;
+use mongodb::options::{
+    Collation, DeleteManyModel, DeleteOneModel, InsertOneModel, ReplaceOneModel, UpdateManyModel,
+    UpdateModifications, UpdateOneModel, WriteModel,
+};
+use mongodb::Namespace;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Records handed to a worker at a time.
+const CHUNK_ROWS: usize = 256;
+
+/// A record as read from the input, before it is decoded.
+struct RawRow {
+    row_num: usize,
+    raw: RawRecord,
+    end: RowEnd,
+    read_error: Option<String>,
+}
+
+/// What a worker made of a record.
+enum Processed {
+    /// The writes of the row, usually one.
+    Rendered(Vec<RenderedWrite>),
+    Skipped,
+    Failed(Stage, String),
+}
+
+/// A rendered document and the operation that writes it.
+struct RenderedWrite {
+    operation: String,
+    /// Collection in the target database, when not `--collection`.
+    collection: Option<String>,
+    doc: Document,
+}
+
+struct ProcessedRow {
+    row_num: usize,
+    raw: RawRecord,
+    end: RowEnd,
+    result: Processed,
+}
+
+/// How CSV records are turned into named fields.
+#[derive(Default)]
+pub struct CsvColumns {
+    pub headers: Option<StringRecord>,
+    /// Input columns that are ignored, such as `_reject_*` columns.
+    pub skip: Vec<usize>,
+    pub expected_len: usize,
+    pub flexible: bool,
+}
+
+impl CsvColumns {
+    /// The names records are mapped by, or `None` for input without columns,
+    /// such as JSON.
+    pub fn names(&self) -> Option<Vec<String>> {
+        match &self.headers {
+            Some(headers) => Some(
+                headers
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| !self.skip.contains(i))
+                    .map(|(_, name)| name.to_string())
+                    .collect(),
+            ),
+            None if self.expected_len > 0 => Some((0..self.expected_len).map(|i| format!("col_{}", i)).collect()),
+            None => None,
+        }
+    }
+}
+
+/// A row that failed, and the stage at which it did.
+type RowError = (Stage, String);
+
+/// A mapped record and its value of the routing column, if any.
+type Mapped = (Document, Option<String>);
+
+/// Everything a worker needs to turn a record into a rendered document.
+/// One is built for each input file; the mapping and templates are shared.
+pub struct RowContext {
+    pub columns: CsvColumns,
+    pub field_mapping: Arc<FieldMapping>,
+    pub env: Arc<Environment<'static>>,
+    pub operation: Operation,
+    /// Routed values to operation or template names.
+    pub op_map: HashMap<String, String>,
+    pub raw_insert: bool,
+    pub extended_json: bool,
+    pub validate_only: bool,
+    /// The input file, as given on the command line.
+    pub file: String,
+    pub run_id: String,
+    /// Field that receives the file, row number and run id of each document.
+    pub provenance: Option<String>,
+}
+
+impl RowContext {
+    fn map_csv(&self, row_num: usize, raw: &ByteRecord) -> Result<Mapped, RowError> {
+        let columns = &self.columns;
+        if !columns.flexible && raw.len() != columns.expected_len {
+            return Err((
+                Stage::Parse,
+                format!(
+                    "Row {}: CSV read error: expected {} fields, found {}",
+                    row_num,
+                    columns.expected_len,
+                    raw.len()
+                ),
+            ));
+        }
+
+        let result = StringRecord::from_byte_record(raw.clone())
+            .map_err(|e| (Stage::Parse, format!("Row {}: CSV read error: {}", row_num, e)))?;
+
+        let mut record: HashMap<String, String> = match &columns.headers {
+            Some(hdrs) => hdrs
+                .iter()
+                .zip(result.iter())
+                .enumerate()
+                .filter(|(i, _)| !columns.skip.contains(i))
+                .map(|(_, (k, v))| (k.to_string(), v.to_string()))
+                .collect(),
+            None => result
+                .iter()
+                .enumerate()
+                .map(|(i, val)| (format!("col_{}", i), val.to_string()))
+                .collect(),
+        };
+
+        let op_value = match self.routing_column() {
+            Some(column) if self.field_mapping.0.contains_key(column) => record.get(column).cloned(),
+            Some(column) => record.remove(column),
+            None => None,
+        };
+
+        validate_required_fields(&record, &self.field_mapping)
+            .map_err(|e| (Stage::Validate, format!("Row {}: {}", row_num, e)))?;
+
+        let doc = apply_mapping(&record, &self.field_mapping, row_num).map_err(|e| (Stage::Map, e.to_string()))?;
+        Ok((doc, op_value))
+    }
+
+    /// JSON values are read as Extended JSON, so `{"$oid": ...}` and
+    /// `{"$date": ...}` arrive as ObjectIds and dates.
+    fn map_json(&self, row_num: usize, text: &[u8]) -> Result<Mapped, RowError> {
+        let parse_error = |e: String| (Stage::Parse, format!("Row {}: JSON read error: {}", row_num, e));
+        let value: serde_json::Value = serde_json::from_slice(text).map_err(|e| parse_error(e.to_string()))?;
+        match Bson::try_from(value) {
+            Ok(Bson::Document(doc)) => self.map_document(row_num, doc),
+            Ok(_) => Err(parse_error("record is not an object".to_string())),
+            Err(e) => Err(parse_error(e.to_string())),
+        }
+    }
+
+    /// Maps a decoded record, from JSON or Parquet, keeping its BSON types.
+    fn map_document(&self, row_num: usize, mut record: Document) -> Result<Mapped, RowError> {
+        for column in REJECT_COLUMNS {
+            record.remove(column);
+        }
+
+        let op_value = match self.routing_column() {
+            Some(column) if self.field_mapping.0.contains_key(column) => record.get(column).cloned(),
+            Some(column) => record.remove(column),
+            None => None,
+        };
+        let op_value = match op_value {
+            Some(Bson::String(name)) => Some(name),
+            _ => None,
+        };
+
+        validate_required_document(&record, &self.field_mapping)
+            .map_err(|e| (Stage::Validate, format!("Row {}: {}", row_num, e)))?;
+
+        let doc = apply_mapping_document(record, &self.field_mapping, row_num).map_err(|e| (Stage::Map, e.to_string()))?;
+        Ok((doc, op_value))
+    }
+
+    /// The column naming each row's operation. It is read from the record
+    /// before mapping and left out of the document unless a field maps it.
+    fn routing_column(&self) -> Option<&str> {
+        match &self.operation {
+            Operation::Column(column) => Some(column),
+            _ => None,
+        }
+    }
+
+    fn process(&self, row: RawRow) -> ProcessedRow {
+        let result = match row.read_error {
+            Some(message) => Processed::Failed(Stage::Parse, message),
+            None => self.render(row.row_num, &row.raw),
+        };
+        ProcessedRow {
+            row_num: row.row_num,
+            raw: row.raw,
+            end: row.end,
+            result,
+        }
+    }
+
+    fn render(&self, row_num: usize, raw: &RawRecord) -> Processed {
+        let mapped = match raw {
+            RawRecord::Csv(raw) => self.map_csv(row_num, raw),
+            RawRecord::Json(text) => self.map_json(row_num, text),
+            RawRecord::Document(doc) => self.map_document(row_num, doc.clone()),
+            RawRecord::Unreadable => Err((Stage::Parse, format!("Row {}: Record could not be read", row_num))),
+        };
+        let (mapped, op_value) = match mapped {
+            Ok(mapped) => mapped,
+            Err((stage, message)) => return Processed::Failed(stage, message),
+        };
+
+        if self.validate_only {
+            return Processed::Skipped;
+        }
+
+        let origin = RowOrigin {
+            file: &self.file,
+            row_num,
+            run_id: &self.run_id,
+        };
+        let rendered = render_operation(
+            &self.env,
+            &self.operation,
+            &self.op_map,
+            &mapped,
+            op_value.as_deref(),
+            self.raw_insert,
+            &origin,
+        );
+        let (operation, rendered_json) = match rendered {
+            Ok(Some(rendered)) => rendered,
+            Ok(None) => return Processed::Skipped,
+            Err(e) => return Processed::Failed(Stage::Render, format!("Row {}: Template error: {}", row_num, e)),
+        };
+
+        let writes = match rendered_json {
+            serde_json::Value::Array(items) => items
+                .into_iter()
+                .enumerate()
+                .map(|(index, item)| match item {
+                    serde_json::Value::Object(_) => self.to_document(item).and_then(|doc| listed_write(index + 1, doc)),
+                    _ => Err(format!("Operation {} of the list is not an object", index + 1)),
+                })
+                .collect::<Result<Vec<_>, String>>(),
+            value if OPERATIONS.contains(&operation.as_str()) => self.to_document(value).map(|doc| {
+                vec![RenderedWrite {
+                    operation,
+                    collection: None,
+                    doc,
+                }]
+            }),
+            _ => Err(format!(
+                "Template '{}' is not named after an operation, so it must render a list of operations",
+                operation
+            )),
+        };
+        let mut writes = match writes {
+            Ok(writes) => writes,
+            Err(message) => return Processed::Failed(Stage::Render, format!("Row {}: {}", row_num, message)),
+        };
+
+        for write in &mut writes {
+            if write.operation == "update" {
+                if let Ok(update_doc) = write.doc.get_document_mut("update") {
+                    update_doc.remove("$setOnInsert");
+                }
+            }
+            if let Some(field) = &self.provenance {
+                self.add_provenance(&mut write.doc, &write.operation, field, row_num);
+            }
+        }
+
+        Processed::Rendered(writes)
+    }
+
+    fn to_document(&self, rendered_json: serde_json::Value) -> Result<Document, String> {
+        if self.extended_json {
+            match bson::to_bson(&rendered_json) {
+                Ok(Bson::Document(doc)) => Ok(doc),
+                _ => Err("Rendered JSON is not a document".to_string()),
+            }
+        } else {
+            match serde_json::to_string(&rendered_json).and_then(|s| serde_json::from_str::<serde_json::Value>(&s)) {
+                Ok(json_val) => bson::to_document(&json_val).map_err(|e| format!("JSON to BSON error: {}", e)),
+                Err(e) => Err(format!("Template render error: {}", e)),
+            }
+        }
+    }
+
+    /// Stores where the row came from in the inserted or replacing document,
+    /// or with `$set` for updates and upserts. Deletes get nothing.
+    fn add_provenance(&self, rendered: &mut Document, operation: &str, field: &str, row_num: usize) {
+        let provenance = bson::doc! {
+            "file": &self.file,
+            "row": row_num as i64,
+            "run_id": &self.run_id,
+        };
+        if operation == "insert" {
+            rendered.insert(field, provenance);
+            return;
+        }
+        if operation == "replace" {
+            if let Ok(replacement) = rendered.get_document_mut("replacement") {
+                replacement.insert(field, provenance);
+            }
+            return;
+        }
+        // A pipeline update gets one more stage.
+        if let Some(Bson::Array(stages)) = rendered.get_mut("update") {
+            stages.push(Bson::Document(bson::doc! { "$set": { field: { "$literal": provenance } } }));
+            return;
+        }
+        let Ok(update) = rendered.get_document_mut("update") else {
+            return;
+        };
+        if !matches!(update.get("$set"), Some(Bson::Document(_))) {
+            update.insert("$set", Document::new());
+        }
+        if let Ok(set) = update.get_document_mut("$set") {
+            set.insert(field, provenance);
+        }
+    }
+}
+
+/// One entry of a rendered list of operations: an object with its own
+/// `op`, an optional `collection`, and a `document` for inserts or the
+/// keys of a single-operation template otherwise.
+fn listed_write(position: usize, mut doc: Document) -> Result<RenderedWrite, String> {
+    let operation = match doc.remove("op") {
+        Some(Bson::String(op)) if OPERATIONS.contains(&op.as_str()) => op,
+        Some(Bson::String(op)) => return Err(format!("Operation {} of the list: unknown op '{}'", position, op)),
+        _ => return Err(format!("Operation {} of the list has no `op`", position)),
+    };
+    let collection = match doc.remove("collection") {
+        None => None,
+        Some(Bson::String(name)) if !name.is_empty() => Some(name),
+        Some(_) => return Err(format!("Operation {} of the list: `collection` must be a name", position)),
+    };
+    if operation == "insert" {
+        doc = match doc.remove("document") {
+            Some(Bson::Document(document)) => document,
+            _ => return Err(format!("Operation {} of the list is an insert without a `document`", position)),
+        };
+    }
+    Ok(RenderedWrite {
+        operation,
+        collection,
+        doc,
+    })
+}
+
+/// How rows are turned into writes.
+pub struct PipelineOptions {
+    pub workers: usize,
+    pub max_inflight_batches: usize,
+    /// 0 writes every row on its own.
+    pub batch_size: usize,
+    /// Hand rows to the writers in input order and write one batch at a time.
+    pub ordered: bool,
+    pub dry_run: bool,
+    pub debug: bool,
+    pub max_errors: Option<usize>,
+    pub namespace: Namespace,
+    /// Rows already imported by an earlier run; numbering continues after them.
+    pub start_row: usize,
+}
+
+/// Imports one input. `writer` is `None` when nothing is written, as with
+/// `--dry-run` and `--validate-only`. With a `checkpoint`, its file is
+/// updated after every batch.
+pub async fn run(
+    source: Box<dyn RecordSource>,
+    ctx: RowContext,
+    options: PipelineOptions,
+    writer: Option<BatchWriter>,
+    checkpoint: &mut Option<CheckpointTracker>,
+    rejects: &mut Option<RejectWriter>,
+    summary: &mut ImportSummary,
+) -> Result<()> {
+    let workers = options.workers.max(1);
+    let ctx = Arc::new(ctx);
+
+    let (raw_tx, raw_rx) = sync_channel(workers * 2);
+    let start_row = options.start_row;
+    let reader = std::thread::spawn(move || read_chunks(source, start_row, raw_tx));
+
+    let raw_rx = Arc::new(Mutex::new(raw_rx));
+    let (done_tx, mut done_rx) = mpsc::channel(workers * 2);
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let ctx = ctx.clone();
+            let raw_rx = raw_rx.clone();
+            let done_tx = done_tx.clone();
+            std::thread::spawn(move || process_chunks(&ctx, &raw_rx, &done_tx))
+        })
+        .collect();
+    drop(raw_rx);
+    drop(done_tx);
+
+    let mut coordinator = Coordinator {
+        max_inflight: if options.ordered { 1 } else { options.max_inflight_batches.max(1) },
+        batch_size: options.batch_size.max(1),
+        ctx,
+        options,
+        writer,
+        checkpoint,
+        rejects,
+        summary,
+        models: Vec::new(),
+        rows: Vec::new(),
+        inflight: FuturesUnordered::new(),
+    };
+
+    // Chunks finish out of order; in ordered mode they wait here for their turn.
+    let mut pending: BTreeMap<usize, Vec<ProcessedRow>> = BTreeMap::new();
+    let mut next_seq = 0;
+
+    'receive: while let Some((seq, rows)) = done_rx.recv().await {
+        let ready = if coordinator.options.ordered {
+            pending.insert(seq, rows);
+            let mut ready = Vec::new();
+            while let Some(rows) = pending.remove(&next_seq) {
+                ready.push(rows);
+                next_seq += 1;
+            }
+            ready
+        } else {
+            vec![rows]
+        };
+
+        for row in ready.into_iter().flatten() {
+            if coordinator.summary.exceeds(coordinator.options.max_errors) {
+                break 'receive;
+            }
+            coordinator.handle(row).await?;
+        }
+    }
+
+    // Closing the channel stops the workers, which in turn stops the reader.
+    drop(done_rx);
+    coordinator.finish().await?;
+
+    for handle in handles {
+        handle.join().map_err(|_| anyhow!("Mapping worker panicked"))?;
+    }
+    reader.join().map_err(|_| anyhow!("CSV reader thread panicked"))?;
+    Ok(())
+}
+
+fn read_chunks(mut source: Box<dyn RecordSource>, start_row: usize, tx: SyncSender<(usize, Vec<RawRow>)>) {
+    let mut chunk = Vec::with_capacity(CHUNK_ROWS);
+    let mut seq = 0;
+    let mut row_num = start_row;
+
+    while let Some(result) = source.next_record() {
+        row_num += 1;
+        let (raw, read_error) = match result {
+            Ok(raw) => (raw, None),
+            Err(e) => (RawRecord::Unreadable, Some(format!("Row {}: Read error: {}", row_num, e))),
+        };
+        chunk.push(RawRow {
+            row_num,
+            raw,
+            end: source.end(),
+            read_error,
+        });
+
+        if chunk.len() == CHUNK_ROWS {
+            if tx.send((seq, std::mem::take(&mut chunk))).is_err() {
+                return;
+            }
+            seq += 1;
+        }
+    }
+
+    if !chunk.is_empty() {
+        let _ = tx.send((seq, chunk));
+    }
+}
+
+fn process_chunks(
+    ctx: &RowContext,
+    rx: &Mutex<Receiver<(usize, Vec<RawRow>)>>,
+    tx: &mpsc::Sender<(usize, Vec<ProcessedRow>)>,
+) {
+    loop {
+        let received = rx.lock().map_err(|_| ()).and_then(|rx| rx.recv().map_err(|_| ()));
+        let Ok((seq, rows)) = received else {
+            return;
+        };
+        let processed = rows.into_iter().map(|row| ctx.process(row)).collect();
+        if tx.blocking_send((seq, processed)).is_err() {
+            return;
+        }
+    }
+}
+
+/// A row waiting in, or being written by, a batch.
+struct BatchRow {
+    row_num: usize,
+    raw: RawRecord,
+    end: RowEnd,
+    /// Models of the row in the batch, in order after those of earlier rows.
+    writes: usize,
+}
+
+type BatchRows = Vec<BatchRow>;
+
+/// Batches rendered rows and keeps track of the batches being written.
+struct Coordinator<'a> {
+    ctx: Arc<RowContext>,
+    options: PipelineOptions,
+    writer: Option<BatchWriter>,
+    checkpoint: &'a mut Option<CheckpointTracker>,
+    rejects: &'a mut Option<RejectWriter>,
+    summary: &'a mut ImportSummary,
+    max_inflight: usize,
+    batch_size: usize,
+    models: Vec<RowWrite>,
+    rows: BatchRows,
+    inflight: FuturesUnordered<JoinHandle<(BatchRows, BatchOutcome)>>,
+}
+
+impl Coordinator<'_> {
+    async fn handle(&mut self, row: ProcessedRow) -> Result<()> {
+        self.summary.rows_read += 1;
+
+        let writes = match row.result {
+            Processed::Rendered(writes) => writes,
+            Processed::Skipped => {
+                self.settle(row.row_num, row.end);
+                return Ok(());
+            }
+            Processed::Failed(stage, message) => {
+                eprintln!("{}", message);
+                self.settle(row.row_num, row.end);
+                return self.reject(&row.raw, row.row_num, stage, &message);
+            }
+        };
+
+        if self.options.dry_run || self.options.debug {
+            let labelled = writes.len() > 1 || !matches!(self.ctx.operation, Operation::Fixed(_));
+            for write in &writes {
+                match &write.collection {
+                    Some(collection) => {
+                        println!("Row {} ({} into {}): {:?}", row.row_num, write.operation, collection, write.doc)
+                    }
+                    None if labelled => println!("Row {} ({}): {:?}", row.row_num, write.operation, write.doc),
+                    None => println!("Row {}: {:?}", row.row_num, write.doc),
+                }
+            }
+        }
+
+        // Built for dry runs too, so a template missing its filter shows up.
+        // A row is written in full or not at all, as far as building goes.
+        let mut models = Vec::with_capacity(writes.len());
+        for write in writes {
+            let namespace = match write.collection {
+                Some(coll) => Namespace {
+                    db: self.options.namespace.db.clone(),
+                    coll,
+                },
+                None => self.options.namespace.clone(),
+            };
+            match row_write(&namespace, &write.operation, write.doc) {
+                Ok(model) => models.push(model),
+                Err(e) => {
+                    let message = format!("Row {}: {}", row.row_num, e);
+                    eprintln!("{}", message);
+                    self.settle(row.row_num, row.end);
+                    return self.reject(&row.raw, row.row_num, Stage::Render, &message);
+                }
+            }
+        }
+        if self.writer.is_none() {
+            return Ok(());
+        }
+        self.rows.push(BatchRow {
+            row_num: row.row_num,
+            raw: row.raw,
+            end: row.end,
+            writes: models.len(),
+        });
+        self.models.extend(models);
+
+        if self.models.len() >= self.batch_size {
+            self.dispatch().await?;
+        }
+        Ok(())
+    }
+
+    /// Starts writing the current batch, first waiting for a free slot.
+    async fn dispatch(&mut self) -> Result<()> {
+        let Some(writer) = self.writer.clone() else {
+            return Ok(());
+        };
+        while self.inflight.len() >= self.max_inflight {
+            self.settle_next().await?;
+        }
+
+        let models = std::mem::take(&mut self.models);
+        let rows = std::mem::take(&mut self.rows);
+        self.inflight.push(tokio::spawn(async move {
+            let outcome = writer.write(models).await;
+            (rows, outcome)
+        }));
+        Ok(())
+    }
+
+    /// Waits for one in-flight batch and records its results.
+    async fn settle_next(&mut self) -> Result<()> {
+        let Some(joined) = self.inflight.next().await else {
+            return Ok(());
+        };
+        let (rows, outcome) = joined.map_err(|e| anyhow!("Batch writer task failed: {}", e))?;
+        self.summary.writes.add(&outcome.counts);
+        let last_row = rows.last().map(|row| row.row_num).unwrap_or(0);
+        for message in &outcome.write_concern_errors {
+            eprintln!("Write concern error at row {}: {}", last_row, message);
+        }
+
+        if let Some(failure) = outcome.failure {
+            if rows.len() == 1 {
+                eprintln!("Row {}: MongoDB write error: {}", last_row, failure.message);
+            } else {
+                eprintln!("Bulk write error at row {}: {}", last_row, failure.message);
+            }
+            // Rows with several writes are rejected once, for their first failed write.
+            let owners: Vec<usize> = rows.iter().enumerate().flat_map(|(i, row)| vec![i; row.writes]).collect();
+            let mut rejected = None;
+            for (index, message) in &failure.failed {
+                let owner = owners[*index];
+                if rejected == Some(owner) {
+                    continue;
+                }
+                rejected = Some(owner);
+                let row = &rows[owner];
+                self.reject(&row.raw, row.row_num, Stage::Write, message)?;
+            }
+        }
+
+        for row in &rows {
+            self.settle(row.row_num, row.end);
+        }
+        self.save_checkpoint()
+    }
+
+    /// Writes what is left of the last batch and waits for every batch in flight.
+    async fn finish(&mut self) -> Result<()> {
+        if !self.models.is_empty() {
+            self.dispatch().await?;
+        }
+        while !self.inflight.is_empty() {
+            self.settle_next().await?;
+        }
+        self.save_checkpoint()
+    }
+
+    /// Records that a row has been written or rejected.
+    fn settle(&mut self, row_num: usize, end: RowEnd) {
+        if let Some(checkpoint) = self.checkpoint {
+            checkpoint.settle(row_num, end);
+        }
+    }
+
+    /// Saves the checkpoint once the rejects it covers are on disk.
+    fn save_checkpoint(&mut self) -> Result<()> {
+        let Some(checkpoint) = self.checkpoint else {
+            return Ok(());
+        };
+        if let Some(writer) = self.rejects {
+            writer.flush()?;
+        }
+        checkpoint.save()
+    }
+
+    /// Counts a failed row and appends it to the reject file, if one was requested.
+    fn reject(&mut self, raw: &RawRecord, row_num: usize, stage: Stage, message: &str) -> Result<()> {
+        self.summary.record_failure(stage);
+        match self.rejects {
+            Some(writer) => writer.reject(raw, row_num, stage, strip_row_prefix(message)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Options a template can give next to its `filter`.
+#[derive(Default)]
+struct TemplateOptions {
+    array_filters: Option<Vec<Bson>>,
+    collation: Option<Document>,
+    hint: Option<Bson>,
+    sort: Option<Document>,
+    let_vars: Option<Document>,
+}
+
+/// Builds the write for a rendered document. Inserts take the document as it
+/// is; the other operations read `filter` and `update` or `replacement` from
+/// it, `replace` and `update_many` an optional `upsert`, and any of
+/// `arrayFilters`, `collation`, `hint`, `sort` and `let` that apply.
+fn row_write(namespace: &Namespace, operation: &str, rendered: Document) -> Result<RowWrite> {
+    let namespace = namespace.clone();
+    let options = match operation {
+        "insert" => TemplateOptions::default(),
+        _ => template_options(&rendered, operation)?,
+    };
+    let model = match operation {
+        "insert" => WriteModel::InsertOne(InsertOneModel::builder().namespace(namespace).document(rendered).build()),
+        "upsert" | "update" => {
+            let filter = rendered.get_document("filter").cloned().unwrap_or_default();
+            let update = update_modifications(&rendered, operation)?;
+            WriteModel::UpdateOne(
+                UpdateOneModel::builder()
+                    .namespace(namespace)
+                    .filter(filter)
+                    .update(update)
+                    .upsert(operation == "upsert")
+                    .array_filters(options.array_filters)
+                    .collation(options.collation)
+                    .hint(options.hint)
+                    .sort(options.sort)
+                    .build(),
+            )
+        }
+        "update_many" => WriteModel::UpdateMany(
+            UpdateManyModel::builder()
+                .namespace(namespace)
+                .filter(required_filter(&rendered, operation)?)
+                .update(update_modifications(&rendered, operation)?)
+                .upsert(rendered.get_bool("upsert").ok())
+                .array_filters(options.array_filters)
+                .collation(options.collation)
+                .hint(options.hint)
+                .build(),
+        ),
+        "replace" => WriteModel::ReplaceOne(
+            ReplaceOneModel::builder()
+                .namespace(namespace)
+                .filter(required_filter(&rendered, operation)?)
+                .replacement(required_document(&rendered, operation, "replacement")?)
+                .upsert(rendered.get_bool("upsert").ok())
+                .collation(options.collation)
+                .hint(options.hint)
+                .sort(options.sort)
+                .build(),
+        ),
+        "delete" => WriteModel::DeleteOne(
+            DeleteOneModel::builder()
+                .namespace(namespace)
+                .filter(required_filter(&rendered, operation)?)
+                .collation(options.collation)
+                .hint(options.hint)
+                .build(),
+        ),
+        "delete_many" => WriteModel::DeleteMany(
+            DeleteManyModel::builder()
+                .namespace(namespace)
+                .filter(required_filter(&rendered, operation)?)
+                .collation(options.collation)
+                .hint(options.hint)
+                .build(),
+        ),
+        _ => return Err(anyhow!("Unsupported operation '{}'.", operation)),
+    };
+    Ok(RowWrite {
+        model,
+        let_vars: options.let_vars,
+    })
+}
+
+/// The `update` of a template: a document of update operators, or an array
+/// of stages for an aggregation pipeline update.
+fn update_modifications(rendered: &Document, operation: &str) -> Result<UpdateModifications> {
+    match rendered.get("update") {
+        Some(Bson::Document(update)) => Ok(UpdateModifications::Document(update.clone())),
+        Some(Bson::Array(stages)) => stages
+            .iter()
+            .map(|stage| match stage {
+                Bson::Document(stage) => Ok(stage.clone()),
+                _ => Err(anyhow!("Every stage of an `update` pipeline must be a document")),
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(UpdateModifications::Pipeline),
+        Some(_) => Err(anyhow!("The `update` of operation '{}' must be a document or a pipeline array", operation)),
+        None => Err(anyhow!("Operation '{}' needs an `update` in its template", operation)),
+    }
+}
+
+/// Reads `arrayFilters`, `collation`, `hint`, `sort` and `let`, refusing the
+/// ones the operation cannot use instead of dropping them.
+fn template_options(rendered: &Document, operation: &str) -> Result<TemplateOptions> {
+    let unsupported = [
+        ("arrayFilters", matches!(operation, "update" | "upsert" | "update_many")),
+        ("sort", matches!(operation, "update" | "upsert" | "replace")),
+    ];
+    for (key, supported) in unsupported {
+        if !supported && rendered.contains_key(key) {
+            return Err(anyhow!("`{}` does not apply to operation '{}'", key, operation));
+        }
+    }
+
+    let array_filters = match rendered.get("arrayFilters") {
+        None => None,
+        Some(Bson::Array(filters)) if filters.iter().all(|f| matches!(f, Bson::Document(_))) => Some(filters.clone()),
+        Some(_) => return Err(anyhow!("`arrayFilters` must be an array of documents")),
+    };
+    let collation = optional_document(rendered, "collation")?;
+    if let Some(collation) = &collation {
+        bson::from_document::<Collation>(collation.clone()).map_err(|e| anyhow!("Invalid `collation`: {}", e))?;
+    }
+    let hint = match rendered.get("hint") {
+        None => None,
+        Some(hint @ (Bson::String(_) | Bson::Document(_))) => Some(hint.clone()),
+        Some(_) => return Err(anyhow!("`hint` must be an index name or a key pattern document")),
+    };
+    Ok(TemplateOptions {
+        array_filters,
+        collation,
+        hint,
+        sort: optional_document(rendered, "sort")?,
+        let_vars: optional_document(rendered, "let")?,
+    })
+}
+
+fn optional_document(rendered: &Document, key: &str) -> Result<Option<Document>> {
+    match rendered.get(key) {
+        None => Ok(None),
+        Some(Bson::Document(doc)) => Ok(Some(doc.clone())),
+        Some(_) => Err(anyhow!("`{}` must be a document", key)),
+    }
+}
+
+fn required_document(rendered: &Document, operation: &str, key: &str) -> Result<Document> {
+    rendered
+        .get_document(key)
+        .cloned()
+        .map_err(|_| anyhow!("Operation '{}' needs a `{}` document in its template", operation, key))
+}
+
+/// The `filter` of a write that may touch, or remove, many documents. An
+/// empty filter would match the whole collection, so it is refused.
+fn required_filter(rendered: &Document, operation: &str) -> Result<Document> {
+    let filter = required_document(rendered, operation, "filter")?;
+    if filter.is_empty() {
+        return Err(anyhow!("Operation '{}' needs a non-empty `filter`", operation));
+    }
+    Ok(filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn listed_error(position: usize, doc: Document) -> String {
+        listed_write(position, doc).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn listed_inserts_write_their_document() {
+        let write = listed_write(1, doc! { "op": "insert", "collection": "audit", "document": { "a": 1 } }).unwrap();
+        assert_eq!(write.operation, "insert");
+        assert_eq!(write.collection.as_deref(), Some("audit"));
+        assert_eq!(write.doc, doc! { "a": 1 });
+    }
+
+    #[test]
+    fn listed_operations_keep_their_template_keys() {
+        let write = listed_write(2, doc! { "op": "upsert", "filter": { "_id": 1 }, "update": { "$set": { "a": 1 } } }).unwrap();
+        assert_eq!(write.operation, "upsert");
+        assert_eq!(write.collection, None);
+        assert_eq!(write.doc, doc! { "filter": { "_id": 1 }, "update": { "$set": { "a": 1 } } });
+    }
+
+    #[test]
+    fn listed_operations_must_be_complete() {
+        assert_eq!(listed_error(1, doc! { "document": {} }), "Operation 1 of the list has no `op`");
+        assert_eq!(listed_error(2, doc! { "op": "merge" }), "Operation 2 of the list: unknown op 'merge'");
+        assert_eq!(
+            listed_error(3, doc! { "op": "delete", "collection": "" }),
+            "Operation 3 of the list: `collection` must be a name"
+        );
+        assert_eq!(listed_error(4, doc! { "op": "insert" }), "Operation 4 of the list is an insert without a `document`");
+    }
+}
@@ -0,0 +1,289 @@
+// src/reject.rs
+use crate::dialect::CsvDialect;
+use crate::input::{compact_json, RawRecord};
+use anyhow::Result;
+use bson::Bson;
+use csv::{ByteRecord, Writer};
+use mongodb::error::{Error, ErrorKind};
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+/// Columns appended to every rejected record. Input columns with these names
+/// are ignored, so a reject file can be fed back through the same mapping.
+pub const REJECT_COLUMNS: [&str; 3] = ["_reject_row", "_reject_stage", "_reject_error"];
+
+/// The step of the import at which a row failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Parse,
+    Validate,
+    Map,
+    Render,
+    Write,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [Stage::Parse, Stage::Validate, Stage::Map, Stage::Render, Stage::Write];
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Parse => "parse",
+            Stage::Validate => "validate",
+            Stage::Map => "map",
+            Stage::Render => "render",
+            Stage::Write => "write",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Dead-letter writer. Each rejected record is written unchanged, in the
+/// input's format, together with the row number, stage and error message:
+/// as extra columns for CSV, as extra keys for JSON.
+pub struct RejectWriter {
+    sink: RejectSink,
+}
+
+enum RejectSink {
+    Csv { writer: Box<Writer<File>>, skip: Vec<usize> },
+    Json(BufWriter<File>),
+}
+
+impl RejectWriter {
+    /// Creates a CSV reject file in the input's dialect. With `append`, as
+    /// when resuming an import, rejects are added to an existing file
+    /// instead of replacing it.
+    pub fn create(path: &str, dialect: &CsvDialect, headers: Option<&ByteRecord>, append: bool) -> Result<Self> {
+        let existing = append && fs::metadata(path).map(|m| m.len() > 0).unwrap_or(false);
+        let mut writer = dialect.writer_builder()?.flexible(true).from_writer(open(path, append)?);
+
+        let skip: Vec<usize> = headers
+            .map(|hdrs| {
+                hdrs.iter()
+                    .enumerate()
+                    .filter(|(_, h)| REJECT_COLUMNS.iter().any(|c| c.as_bytes() == *h))
+                    .map(|(i, _)| i)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if let (Some(hdrs), false) = (headers, existing) {
+            let mut header = filtered(hdrs, &skip);
+            for column in REJECT_COLUMNS {
+                header.push_field(column.as_bytes());
+            }
+            writer.write_byte_record(&header)?;
+            writer.flush()?;
+        }
+
+        Ok(RejectWriter {
+            sink: RejectSink::Csv {
+                writer: Box::new(writer),
+                skip,
+            },
+        })
+    }
+
+    /// Creates a JSON Lines reject file, for JSON input.
+    pub fn json(path: &str, append: bool) -> Result<Self> {
+        Ok(RejectWriter {
+            sink: RejectSink::Json(BufWriter::new(open(path, append)?)),
+        })
+    }
+
+    pub fn reject(&mut self, raw: &RawRecord, row_num: usize, stage: Stage, message: &str) -> Result<()> {
+        match &mut self.sink {
+            RejectSink::Csv { writer, skip } => {
+                let mut record = match raw {
+                    RawRecord::Csv(raw) => filtered(raw, skip),
+                    _ => ByteRecord::new(),
+                };
+                record.push_field(row_num.to_string().as_bytes());
+                record.push_field(stage.to_string().as_bytes());
+                record.push_field(message.as_bytes());
+                writer.write_byte_record(&record)?;
+            }
+            RejectSink::Json(writer) => {
+                writer.write_all(&json_reject(raw, row_num, stage, message))?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        match &mut self.sink {
+            RejectSink::Csv { writer, .. } => writer.flush()?,
+            RejectSink::Json(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+fn open(path: &str, append: bool) -> Result<File> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?)
+}
+
+/// Adds the reject keys to the front of the original JSON object, keeping
+/// its text as it was. Input that is not an object is kept as a string in
+/// `_reject_raw`. Decoded records, such as Parquet rows, are written as
+/// relaxed Extended JSON.
+fn json_reject(raw: &RawRecord, row_num: usize, stage: Stage, message: &str) -> Vec<u8> {
+    let mut line = format!(
+        "{{\"_reject_row\":{},\"_reject_stage\":{},\"_reject_error\":{}",
+        row_num,
+        serde_json::Value::from(stage.to_string()),
+        serde_json::Value::from(message)
+    )
+    .into_bytes();
+
+    let text = match raw {
+        RawRecord::Json(text) => Cow::Borrowed(text.as_slice()),
+        RawRecord::Document(doc) => {
+            Cow::Owned(Bson::Document(doc.clone()).into_relaxed_extjson().to_string().into_bytes())
+        }
+        _ => Cow::Borrowed(&[][..]),
+    };
+    let text = text.as_ref();
+    let is_object = serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(text).is_ok();
+    if is_object {
+        let compact = compact_json(text);
+        let body = &compact[1..];
+        if body != b"}" {
+            line.push(b',');
+        }
+        line.extend_from_slice(body);
+    } else {
+        if !text.is_empty() {
+            line.extend_from_slice(b",\"_reject_raw\":");
+            line.extend_from_slice(serde_json::Value::from(String::from_utf8_lossy(text)).to_string().as_bytes());
+        }
+        line.push(b'}');
+    }
+    line
+}
+
+fn filtered(record: &ByteRecord, skip: &[usize]) -> ByteRecord {
+    record
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !skip.contains(i))
+        .map(|(_, field)| field)
+        .collect()
+}
+
+/// Drops the "Row n: " prefix of an error message; the reject file already
+/// records the row number in its own column.
+pub fn strip_row_prefix(message: &str) -> &str {
+    message
+        .strip_prefix("Row ")
+        .and_then(|rest| rest.split_once(": "))
+        .filter(|(row, _)| row.chars().all(|c| c.is_ascii_digit()))
+        .map(|(_, rest)| rest)
+        .unwrap_or(message)
+}
+
+/// Works out which operations of a failed bulk write were not applied, as
+/// `(index in batch, message)` pairs. In an ordered batch everything after
+/// the first failed write was never attempted.
+pub fn bulk_failures(error: &Error, batch_len: usize, ordered: bool) -> Vec<(usize, String)> {
+    match &*error.kind {
+        ErrorKind::BulkWrite(bulk) if !bulk.write_errors.is_empty() => {
+            let first_failed = bulk.write_errors.keys().min().copied().unwrap_or(0);
+            (0..batch_len)
+                .filter_map(|i| match bulk.write_errors.get(&i) {
+                    Some(write_error) => Some((i, write_error.message.clone())),
+                    None if ordered && i > first_failed => Some((
+                        i,
+                        format!("Not attempted: write {} of the ordered batch failed", first_failed + 1),
+                    )),
+                    None => None,
+                })
+                .collect()
+        }
+        // Only the write concern failed; the documents themselves were written
+        // and the error is reported by `write_concern_errors`.
+        ErrorKind::BulkWrite(bulk) if !bulk.write_concern_errors.is_empty() => Vec::new(),
+        _ => (0..batch_len).map(|i| (i, error.to_string())).collect(),
+    }
+}
+
+/// Messages of the write concern errors of a failed `bulk_write` or
+/// `insert_many`. The writes were applied but not acknowledged as asked.
+pub fn write_concern_errors(error: &Error) -> Vec<String> {
+    match &*error.kind {
+        ErrorKind::BulkWrite(bulk) => bulk.write_concern_errors.iter().map(|e| e.message.clone()).collect(),
+        ErrorKind::InsertMany(insert) => insert.write_concern_error.iter().map(|e| e.message.clone()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+    use mongodb::error::{BulkWriteError, InsertManyError, WriteConcernError, WriteError};
+
+    fn concern_error() -> WriteConcernError {
+        bson::from_document(doc! { "code": 64, "codeName": "WriteConcernFailed", "errmsg": "waiting for replication timed out" })
+            .unwrap()
+    }
+
+    fn bulk_error(write_errors: &[usize], concern: bool) -> Error {
+        let mut bulk = BulkWriteError::default();
+        for &index in write_errors {
+            let error: WriteError = bson::from_document(doc! { "code": 11000, "errmsg": "E11000 duplicate key" }).unwrap();
+            bulk.write_errors.insert(index, error);
+        }
+        if concern {
+            bulk.write_concern_errors.push(concern_error());
+        }
+        ErrorKind::BulkWrite(bulk).into()
+    }
+
+    #[test]
+    fn ordered_bulk_failures_include_the_writes_never_attempted() {
+        let failed = bulk_failures(&bulk_error(&[1], false), 4, true);
+        let indexes: Vec<usize> = failed.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indexes, [1, 2, 3]);
+        assert!(failed[0].1.contains("duplicate key"));
+        assert_eq!(failed[1].1, "Not attempted: write 2 of the ordered batch failed");
+
+        let unordered = bulk_failures(&bulk_error(&[1, 3], false), 4, false);
+        assert_eq!(unordered.iter().map(|(i, _)| *i).collect::<Vec<_>>(), [1, 3]);
+    }
+
+    #[test]
+    fn write_concern_errors_are_reported_without_failing_rows() {
+        let error = bulk_error(&[], true);
+        assert!(bulk_failures(&error, 3, true).is_empty());
+        assert_eq!(write_concern_errors(&error), ["waiting for replication timed out"]);
+
+        let both = bulk_error(&[0], true);
+        assert_eq!(bulk_failures(&both, 1, false).len(), 1);
+        assert_eq!(write_concern_errors(&both).len(), 1);
+
+        let insert: InsertManyError =
+            bson::from_document(doc! { "writeConcernError": bson::to_document(&concern_error()).unwrap() }).unwrap();
+        let error: Error = ErrorKind::InsertMany(insert).into();
+        assert_eq!(write_concern_errors(&error), ["waiting for replication timed out"]);
+    }
+
+    #[test]
+    fn other_errors_fail_the_whole_batch() {
+        let error: Error = ErrorKind::Custom(std::sync::Arc::new("boom")).into();
+        assert_eq!(bulk_failures(&error, 2, false).len(), 2);
+        assert!(write_concern_errors(&error).is_empty());
+        assert_eq!(strip_row_prefix("Row 12: bad date"), "bad date");
+        assert_eq!(strip_row_prefix("Rows: 3"), "Rows: 3");
+    }
+}
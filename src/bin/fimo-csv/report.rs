@@ -0,0 +1,281 @@
+// src/report.rs
+use crate::reject::Stage;
+use anyhow::Result;
+use mongodb::error::{Error, ErrorKind};
+use mongodb::error::PartialBulkWriteResult;
+use mongodb::results::{DeleteResult, SummaryBulkWriteResult, UpdateResult};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+/// Every row was imported.
+pub const EXIT_OK: i32 = 0;
+/// Some rows failed to parse, validate, map or render.
+pub const EXIT_ROW_ERRORS: i32 = 2;
+/// Some writes to MongoDB failed or were not acknowledged by the write concern.
+pub const EXIT_WRITE_ERRORS: i32 = 3;
+/// The run stopped early because `--max-errors` was exceeded.
+pub const EXIT_ABORTED: i32 = 4;
+
+/// Documents written to MongoDB. Each in-flight batch counts its own writes,
+/// which are added to the run's summary once the batch completes.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct WriteCounts {
+    pub inserted: i64,
+    pub matched: i64,
+    pub modified: i64,
+    pub upserted: i64,
+    pub deleted: i64,
+    /// Batches written without the acknowledgement asked for by the write
+    /// concern. Their documents were written, so no row is rejected.
+    pub write_concern_errors: i64,
+}
+
+impl WriteCounts {
+    pub fn add(&mut self, other: &WriteCounts) {
+        self.inserted += other.inserted;
+        self.matched += other.matched;
+        self.modified += other.modified;
+        self.upserted += other.upserted;
+        self.deleted += other.deleted;
+        self.write_concern_errors += other.write_concern_errors;
+    }
+
+    pub fn add_insert(&mut self) {
+        self.inserted += 1;
+    }
+
+    pub fn add_update(&mut self, result: &UpdateResult) {
+        self.matched += result.matched_count as i64;
+        self.modified += result.modified_count as i64;
+        if result.upserted_id.is_some() {
+            self.upserted += 1;
+        }
+    }
+
+    pub fn add_delete(&mut self, result: &DeleteResult) {
+        self.deleted += result.deleted_count as i64;
+    }
+
+    pub fn add_bulk(&mut self, result: &SummaryBulkWriteResult) {
+        self.inserted += result.inserted_count;
+        self.matched += result.matched_count;
+        self.modified += result.modified_count;
+        self.upserted += result.upserted_count;
+        self.deleted += result.deleted_count;
+    }
+
+    /// Counts the writes that did succeed in a failed bulk write.
+    pub fn add_bulk_error(&mut self, error: &Error) {
+        if let ErrorKind::BulkWrite(bulk) = &*error.kind {
+            if let Some(PartialBulkWriteResult::Summary(result)) = &bulk.partial_result {
+                self.add_bulk(result);
+            }
+        }
+    }
+}
+
+/// Counters for one input file of a run.
+#[derive(Debug, Serialize)]
+pub struct FileSummary {
+    pub input: String,
+    pub rows_read: usize,
+    pub rows_failed: usize,
+    pub failed_by_stage: BTreeMap<String, usize>,
+    #[serde(flatten)]
+    pub writes: WriteCounts,
+}
+
+/// Counters for a whole import run, printed at the end and optionally
+/// written as JSON with `--report`.
+#[derive(Debug, Serialize)]
+pub struct ImportSummary {
+    pub status: &'static str,
+    pub exit_code: i32,
+    pub run_id: String,
+    pub rows_read: usize,
+    pub rows_failed: usize,
+    pub failed_by_stage: BTreeMap<String, usize>,
+    #[serde(flatten)]
+    pub writes: WriteCounts,
+    pub elapsed_secs: f64,
+    pub rows_per_sec: f64,
+    pub files: Vec<FileSummary>,
+    #[serde(skip)]
+    started: Instant,
+    #[serde(skip)]
+    aborted: bool,
+}
+
+impl ImportSummary {
+    pub fn start(run_id: &str) -> Self {
+        ImportSummary {
+            status: "running",
+            exit_code: EXIT_OK,
+            run_id: run_id.to_string(),
+            rows_read: 0,
+            rows_failed: 0,
+            failed_by_stage: BTreeMap::new(),
+            writes: WriteCounts::default(),
+            elapsed_secs: 0.0,
+            rows_per_sec: 0.0,
+            files: Vec::new(),
+            started: Instant::now(),
+            aborted: false,
+        }
+    }
+
+    /// Starts counting rows towards `input`.
+    pub fn begin_file(&mut self, input: &str) {
+        self.files.push(FileSummary {
+            input: input.to_string(),
+            rows_read: self.rows_read,
+            rows_failed: self.rows_failed,
+            failed_by_stage: self.failed_by_stage.clone(),
+            writes: self.writes,
+        });
+    }
+
+    /// Turns the totals recorded by `begin_file` into the file's own counts.
+    pub fn end_file(&mut self) {
+        let Some(file) = self.files.last_mut() else {
+            return;
+        };
+        file.rows_read = self.rows_read - file.rows_read;
+        file.rows_failed = self.rows_failed - file.rows_failed;
+        file.failed_by_stage = self
+            .failed_by_stage
+            .iter()
+            .map(|(stage, count)| (stage.clone(), count - file.failed_by_stage.get(stage).copied().unwrap_or(0)))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        file.writes = WriteCounts {
+            inserted: self.writes.inserted - file.writes.inserted,
+            matched: self.writes.matched - file.writes.matched,
+            modified: self.writes.modified - file.writes.modified,
+            upserted: self.writes.upserted - file.writes.upserted,
+            deleted: self.writes.deleted - file.writes.deleted,
+            write_concern_errors: self.writes.write_concern_errors - file.writes.write_concern_errors,
+        };
+    }
+
+    pub fn record_failure(&mut self, stage: Stage) {
+        self.rows_failed += 1;
+        *self.failed_by_stage.entry(stage.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn failures(&self, stage: Stage) -> usize {
+        self.failed_by_stage.get(&stage.to_string()).copied().unwrap_or(0)
+    }
+
+    /// True once more rows failed than `--max-errors` allows.
+    pub fn exceeds(&self, max_errors: Option<usize>) -> bool {
+        max_errors.is_some_and(|max| self.rows_failed > max)
+    }
+
+    pub fn abort(&mut self) {
+        self.aborted = true;
+    }
+
+    /// Stops the clock and settles the status and exit code.
+    pub fn finish(&mut self) {
+        self.elapsed_secs = self.started.elapsed().as_secs_f64();
+        self.rows_per_sec = if self.elapsed_secs > 0.0 {
+            self.rows_read as f64 / self.elapsed_secs
+        } else {
+            0.0
+        };
+        (self.status, self.exit_code) = if self.aborted {
+            ("aborted", EXIT_ABORTED)
+        } else if self.failures(Stage::Write) > 0 || self.writes.write_concern_errors > 0 {
+            ("write_errors", EXIT_WRITE_ERRORS)
+        } else if self.rows_failed > 0 {
+            ("row_errors", EXIT_ROW_ERRORS)
+        } else {
+            ("ok", EXIT_OK)
+        };
+    }
+
+    pub fn print(&self) {
+        println!("📊 Import summary");
+        println!("   Rows read:     {}", self.rows_read);
+        if self.rows_failed > 0 {
+            let stages: Vec<String> = Stage::ALL
+                .iter()
+                .filter(|stage| self.failures(**stage) > 0)
+                .map(|stage| format!("{}: {}", stage, self.failures(*stage)))
+                .collect();
+            println!("   Rows failed:   {} ({})", self.rows_failed, stages.join(", "));
+        } else {
+            println!("   Rows failed:   0");
+        }
+        println!("   Inserted:      {}", self.writes.inserted);
+        println!("   Matched:       {}", self.writes.matched);
+        println!("   Modified:      {}", self.writes.modified);
+        println!("   Upserted:      {}", self.writes.upserted);
+        if self.writes.deleted > 0 {
+            println!("   Deleted:       {}", self.writes.deleted);
+        }
+        if self.writes.write_concern_errors > 0 {
+            println!("   Write concern: {} errors", self.writes.write_concern_errors);
+        }
+        println!("   Elapsed:       {:.2}s ({:.0} rows/s)", self.elapsed_secs, self.rows_per_sec);
+        if self.files.len() > 1 {
+            println!("   Files:");
+            for file in &self.files {
+                println!(
+                    "     {}: {} read, {} failed, {} inserted, {} matched, {} modified, {} upserted{}",
+                    file.input,
+                    file.rows_read,
+                    file.rows_failed,
+                    file.writes.inserted,
+                    file.writes.matched,
+                    file.writes.modified,
+                    file.writes.upserted,
+                    if self.writes.deleted > 0 { format!(", {} deleted", file.writes.deleted) } else { String::new() }
+                );
+            }
+        }
+
+        match self.exit_code {
+            EXIT_OK => println!("✅ Completed import process."),
+            EXIT_ABORTED => println!("❌ Import aborted after {} failed rows.", self.rows_failed),
+            EXIT_WRITE_ERRORS if self.rows_failed == 0 => println!(
+                "⚠️ Completed import process with {} write concern errors.",
+                self.writes.write_concern_errors
+            ),
+            _ => println!("⚠️ Completed import process with {} failed rows.", self.rows_failed),
+        }
+    }
+
+    pub fn write_json(&self, path: &str) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_concern_errors_give_the_write_error_exit_code() {
+        let mut summary = ImportSummary::start("run");
+        summary.rows_read = 10;
+        summary.finish();
+        assert_eq!((summary.status, summary.exit_code), ("ok", EXIT_OK));
+
+        summary.begin_file("a.csv");
+        summary.writes.add(&WriteCounts {
+            inserted: 10,
+            write_concern_errors: 1,
+            ..Default::default()
+        });
+        summary.end_file();
+        summary.finish();
+        assert_eq!((summary.status, summary.exit_code), ("write_errors", EXIT_WRITE_ERRORS));
+        assert_eq!(summary.files[0].writes.write_concern_errors, 1);
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["write_concern_errors"], 1);
+    }
+}
@@ -0,0 +1,300 @@
+// src/schema.rs
+use crate::cli::SchemaArgs;
+use crate::mapping::{load_mapping, scalar_text, FieldDef, FieldMapping, OnEmpty};
+use crate::mongo::{connect, create_indexes};
+use crate::path::{parse_path, PathSegment};
+use crate::transform::convert_scalar;
+use anyhow::{anyhow, Result};
+use bson::{doc, Bson, Document};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A sub-document of the schema, built up from the target paths of the fields.
+#[derive(Default)]
+struct ObjectSchema {
+    properties: BTreeMap<String, Property>,
+    required: BTreeSet<String>,
+    /// Part of an array's `items`, which every position of the array shares,
+    /// so `a[0].b` and `a[1].b` both place `b`.
+    shared: bool,
+}
+
+enum Property {
+    Field(Document),
+    Object(ObjectSchema),
+    Array(Option<Box<Property>>),
+}
+
+/// Prints the `$jsonSchema` validator of a mapping and, with `--apply`, sets
+/// it on the collection and creates the mapping's indexes.
+pub async fn run(args: &SchemaArgs) -> Result<()> {
+    let mapping_text = std::fs::read_to_string(&args.mapping)?;
+    let mapping_file = load_mapping(&mapping_text).map_err(|e| anyhow!("{}: {}", args.mapping, e))?;
+    let schema = json_schema(&mapping_file.fields).map_err(|e| anyhow!("{}: {}", args.mapping, e))?;
+    let validator = doc! { "$jsonSchema": schema };
+
+    let json = serde_json::to_string_pretty(&Bson::Document(validator.clone()).into_relaxed_extjson())?;
+    match &args.output {
+        Some(path) => std::fs::write(path, json + "\n")?,
+        None => println!("{}", json),
+    }
+
+    let (true, Some(uri), Some(db), Some(name)) = (args.apply, &args.mongo_uri, &args.db, &args.collection) else {
+        return Ok(());
+    };
+    let collection = connect(uri, db, name).await?;
+    let database = collection.client().database(db);
+
+    let level = args.validation_level.as_deref().unwrap_or("strict");
+    let action = args.validation_action.as_deref().unwrap_or("error");
+    let exists = database.list_collection_names().filter(doc! { "name": name }).await?.contains(name);
+    let command = if exists {
+        doc! { "collMod": name, "validator": validator, "validationLevel": level, "validationAction": action }
+    } else {
+        doc! { "create": name, "validator": validator, "validationLevel": level, "validationAction": action }
+    };
+    database.run_command(command).await?;
+    println!(
+        "✅ {} the validator of {}.{} (level {}, action {})",
+        if exists { "Updated" } else { "Created the collection with" },
+        db,
+        name,
+        level,
+        action
+    );
+
+    for index in create_indexes(&collection, &mapping_file.indexes).await? {
+        println!("🗂️ Index {} is in place", index);
+    }
+    Ok(())
+}
+
+/// Turns a mapping into a `$jsonSchema` matching the documents the importer
+/// writes: each field's `type` becomes its `bsonType`, `required` fields are
+/// required, and `enum`, `pattern`, `min`/`max` and lengths carry over.
+pub fn json_schema(mapping: &FieldMapping) -> Result<Document> {
+    let mut root = ObjectSchema::default();
+    for (key, def) in &mapping.0 {
+        let segments = match def.target_path() {
+            Some(path) => path?.to_vec(),
+            None => parse_path(key)?,
+        };
+        // A field that may be left out of the document cannot be required.
+        let required = def.required && def.on_empty() != OnEmpty::Omit;
+        root.insert(&segments, field_schema(key, def)?, required)
+            .map_err(|e| anyhow!("Cannot place field '{}' in the schema: {}", key, e))?;
+    }
+    Ok(root.into_document())
+}
+
+impl ObjectSchema {
+    fn insert(&mut self, segments: &[PathSegment], field: Document, required: bool) -> Result<()> {
+        let Some((PathSegment::Key(key), rest)) = segments.split_first() else {
+            return Err(anyhow!("the target path must start with a key"));
+        };
+        if required {
+            self.required.insert(key.clone());
+        }
+        match rest.first() {
+            None if self.properties.contains_key(key) && self.shared => Ok(()),
+            None if self.properties.contains_key(key) => Err(anyhow!("another field is already mapped there")),
+            None => {
+                self.properties.insert(key.clone(), Property::Field(field));
+                Ok(())
+            }
+            Some(next) => {
+                let shared = self.shared;
+                let property = self.properties.entry(key.clone()).or_insert_with(|| match next {
+                    PathSegment::Key(_) => Property::Object(ObjectSchema { shared, ..ObjectSchema::default() }),
+                    PathSegment::Index(_) => Property::Array(None),
+                });
+                property.insert(rest, field, required)
+            }
+        }
+    }
+
+    fn into_document(self) -> Document {
+        let mut schema = doc! { "bsonType": "object" };
+        if !self.required.is_empty() {
+            schema.insert("required", self.required.into_iter().collect::<Vec<_>>());
+        }
+        let properties: Document = self.properties.into_iter().map(|(k, p)| (k, Bson::Document(p.into_document()))).collect();
+        schema.insert("properties", properties);
+        schema
+    }
+}
+
+impl Property {
+    fn insert(&mut self, segments: &[PathSegment], field: Document, required: bool) -> Result<()> {
+        match (self, &segments[0]) {
+            (Property::Object(object), PathSegment::Key(_)) => object.insert(segments, field, required),
+            // Every position of an array shares one `items` schema, and
+            // nothing inside it is required, since positions may be missing.
+            (Property::Array(items), PathSegment::Index(_)) => match (items, segments.get(1)) {
+                (slot @ None, None) => {
+                    *slot = Some(Box::new(Property::Field(field)));
+                    Ok(())
+                }
+                (Some(_), None) => Ok(()),
+                (slot, Some(next)) => {
+                    let element = slot.get_or_insert_with(|| {
+                        Box::new(match next {
+                            PathSegment::Key(_) => Property::Object(ObjectSchema { shared: true, ..ObjectSchema::default() }),
+                            PathSegment::Index(_) => Property::Array(None),
+                        })
+                    });
+                    element.insert(&segments[1..], field, false)
+                }
+            },
+            _ => Err(anyhow!("another field is already mapped there")),
+        }
+    }
+
+    fn into_document(self) -> Document {
+        match self {
+            Property::Field(field) => field,
+            Property::Object(object) => object.into_document(),
+            Property::Array(None) => doc! { "bsonType": "array" },
+            Property::Array(Some(items)) => doc! { "bsonType": "array", "items": items.into_document() },
+        }
+    }
+}
+
+/// The schema of one mapped field.
+fn field_schema(key: &str, def: &FieldDef) -> Result<Document> {
+    let nullable = def.on_empty() == OnEmpty::Null;
+    let mut schema = if def.r#type == "array" {
+        let element_type = def.element_type.as_deref().unwrap_or("string");
+        let mut items = doc! { "bsonType": bson_type(element_type) };
+        add_constraints(&mut items, key, def, element_type, false)?;
+        doc! { "bsonType": "array", "items": items }
+    } else {
+        let mut schema = doc! { "bsonType": bson_type(&def.r#type) };
+        add_constraints(&mut schema, key, def, &def.r#type, nullable)?;
+        schema
+    };
+    if nullable {
+        let types = match schema.get("bsonType") {
+            Some(Bson::Array(types)) => types.iter().cloned().chain([Bson::from("null")]).collect(),
+            Some(single) => vec![single.clone(), Bson::from("null")],
+            None => vec![Bson::from("null")],
+        };
+        schema.insert("bsonType", types);
+    }
+    Ok(schema)
+}
+
+/// Mapping types are named like BSON type aliases. Rows rendered through
+/// templates pass through Extended JSON, which reads back every integer that
+/// fits in 32 bits as `int`, so `int` and `long` fields accept both.
+fn bson_type(field_type: &str) -> Bson {
+    match field_type {
+        "int" | "long" => Bson::Array(vec!["int".into(), "long".into()]),
+        other => other.into(),
+    }
+}
+
+/// Adds `enum`, `pattern`, `minimum`/`maximum` and `minLength`/`maxLength`,
+/// with values converted the way the importer converts cells.
+fn add_constraints(schema: &mut Document, key: &str, def: &FieldDef, value_type: &str, nullable: bool) -> Result<()> {
+    let convert = |text: &str| convert_scalar(text, value_type, def, key, 0);
+    if let Some(allowed) = &def.r#enum {
+        let mut values = allowed.iter().filter_map(scalar_text).map(|v| convert(&v)).collect::<Result<Vec<Bson>>>()?;
+        if nullable {
+            values.push(Bson::Null);
+        }
+        schema.insert("enum", values);
+    }
+    if value_type == "string" {
+        if let Some(pattern) = &def.pattern {
+            schema.insert("pattern", pattern.as_str());
+        }
+        if let Some(min) = def.min_length {
+            schema.insert("minLength", min as i64);
+        }
+        if let Some(max) = def.max_length {
+            schema.insert("maxLength", max as i64);
+        }
+    }
+    // $jsonSchema only compares numbers.
+    if matches!(value_type, "int" | "long" | "double" | "decimal") {
+        if let Some(min) = def.min.as_ref().and_then(scalar_text) {
+            schema.insert("minimum", convert(&min)?);
+        }
+        if let Some(max) = def.max.as_ref().and_then(scalar_text) {
+            schema.insert("maximum", convert(&max)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(yaml: &str) -> Document {
+        json_schema(&load_mapping(yaml).unwrap().fields).unwrap()
+    }
+
+    #[test]
+    fn nested_targets_become_sub_documents() {
+        let schema = schema(
+            "name:\n  type: string\n  required: true\n  max_length: 40\ncity:\n  type: string\n  target: address.city\n  required: true\nzip:\n  type: string\n  target: address.zip\n",
+        );
+        assert_eq!(
+            schema,
+            doc! {
+                "bsonType": "object",
+                "required": ["address", "name"],
+                "properties": {
+                    "address": {
+                        "bsonType": "object",
+                        "required": ["city"],
+                        "properties": { "city": { "bsonType": "string" }, "zip": { "bsonType": "string" } },
+                    },
+                    "name": { "bsonType": "string", "maxLength": 40_i64 },
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn array_positions_share_one_items_schema() {
+        let schema = schema(
+            "phone:\n  type: string\n  target: phones[0].number\n  required: true\nfax:\n  type: string\n  target: phones[1].number\ntags:\n  type: array\n  element_type: long\n  min: 0\n",
+        );
+        let properties = schema.get_document("properties").unwrap();
+        assert_eq!(
+            properties.get_document("phones").unwrap(),
+            &doc! {
+                "bsonType": "array",
+                "items": { "bsonType": "object", "properties": { "number": { "bsonType": "string" } } },
+            }
+        );
+        assert_eq!(
+            properties.get_document("tags").unwrap(),
+            &doc! { "bsonType": "array", "items": { "bsonType": ["int", "long"], "minimum": 0_i64 } }
+        );
+        assert_eq!(schema.get_array("required").unwrap(), &vec![Bson::from("phones")]);
+    }
+
+    #[test]
+    fn on_empty_decides_required_and_null() {
+        let schema = schema(
+            "a:\n  type: int\n  required: true\n  on_empty: omit\nb:\n  type: double\n  required: true\n  on_empty: null\nc:\n  type: string\n  on_empty: null\n  enum: [x, y]\n",
+        );
+        assert_eq!(schema.get_array("required").unwrap(), &vec![Bson::from("b")]);
+        let properties = schema.get_document("properties").unwrap();
+        assert_eq!(properties.get_document("a").unwrap(), &doc! { "bsonType": ["int", "long"] });
+        assert_eq!(properties.get_document("b").unwrap(), &doc! { "bsonType": ["double", "null"] });
+        assert_eq!(
+            properties.get_document("c").unwrap(),
+            &doc! { "bsonType": ["string", "null"], "enum": ["x", "y", Bson::Null] }
+        );
+    }
+
+    #[test]
+    fn conflicting_targets_are_refused() {
+        let mapping = load_mapping("a:\n  type: string\n  target: x.y\nb:\n  type: string\n  target: x\n").unwrap();
+        assert!(json_schema(&mapping.fields).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+// src/sheet.rs
+use crate::checkpoint::RowEnd;
+use crate::input::{RawRecord, RecordSource};
+use anyhow::{anyhow, Result};
+use bson::{Bson, DateTime, Document};
+use calamine::{open_workbook_auto, Data, Range, Reader};
+use chrono::{NaiveDate, NaiveDateTime};
+
+/// Which part of a workbook to import.
+pub struct SheetSelection<'a> {
+    /// Sheet name, or its position starting at 1. `None` is the first sheet.
+    pub sheet: Option<&'a str>,
+    /// Cell range in A1 notation, e.g. `B3:H200`, or just its top-left cell.
+    pub range: Option<&'a str>,
+    /// Rows above the header, counted from the top of the range.
+    pub skip_rows: u32,
+    pub headers: bool,
+}
+
+/// Reads the rows of one worksheet as documents. Workbooks are read whole,
+/// so only the selected sheet is kept in memory.
+pub struct SheetSource {
+    cells: Range<Data>,
+    headers: Vec<String>,
+    first_col: u32,
+    next_row: u32,
+    last_row: u32,
+    /// Data rows read so far, used as the checkpoint offset.
+    read: u64,
+}
+
+impl SheetSource {
+    /// Opens the selected sheet, skipping the first `start` data rows.
+    pub fn open(path: &str, selection: &SheetSelection, start: u64) -> Result<Self> {
+        let mut workbook = open_workbook_auto(path).map_err(|e| anyhow!("{}: {}", path, e))?;
+        let names = workbook.sheet_names();
+        let name = match selection.sheet {
+            None => names.first().cloned(),
+            Some(sheet) if names.iter().any(|n| n == sheet) => Some(sheet.to_string()),
+            Some(sheet) => sheet
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|i| names.get(i).cloned()),
+        }
+        .ok_or_else(|| {
+            anyhow!(
+                "Sheet '{}' not found in {} (sheets: {})",
+                selection.sheet.unwrap_or("1"),
+                path,
+                names.join(", ")
+            )
+        })?;
+        let used = workbook.worksheet_range(&name).map_err(|e| anyhow!("{}: {}", path, e))?;
+
+        let (top, left, bottom, right) = match selection.range {
+            Some(range) => {
+                let ((top, left), end) = parse_range(range)?;
+                let (bottom, right) = end.or(used.end()).unwrap_or((top, left));
+                (top, left, bottom, right)
+            }
+            None => match (used.start(), used.end()) {
+                (Some((_, left)), Some((bottom, right))) => (0, left, bottom, right),
+                _ => (0, 0, 0, 0),
+            },
+        };
+        let cells = used.range((top, left), (bottom.max(top), right.max(left)));
+
+        let header_row = top + selection.skip_rows;
+        let headers = (left..=right)
+            .map(|col| {
+                let name = match cells.get_value((header_row, col)) {
+                    Some(cell) if selection.headers && !matches!(cell, Data::Empty) => cell.to_string(),
+                    _ => String::new(),
+                };
+                if name.is_empty() {
+                    format!("col_{}", col - left)
+                } else {
+                    name
+                }
+            })
+            .collect();
+
+        let mut source = SheetSource {
+            cells,
+            headers,
+            first_col: left,
+            next_row: if selection.headers { header_row + 1 } else { header_row },
+            last_row: bottom,
+            read: 0,
+        };
+        while source.read < start && source.next_row().is_some() {}
+        Ok(source)
+    }
+
+    /// Moves to the next row that has at least one value.
+    fn next_row(&mut self) -> Option<u32> {
+        while self.next_row <= self.last_row {
+            let row = self.next_row;
+            self.next_row += 1;
+            let empty = (0..self.headers.len() as u32)
+                .all(|i| matches!(self.cells.get_value((row, self.first_col + i)), None | Some(Data::Empty)));
+            if !empty {
+                self.read += 1;
+                return Some(row);
+            }
+        }
+        None
+    }
+}
+
+impl RecordSource for SheetSource {
+    fn next_record(&mut self) -> Option<Result<RawRecord>> {
+        let row = self.next_row()?;
+        let mut doc = Document::new();
+        for (i, header) in self.headers.iter().enumerate() {
+            let col = self.first_col + i as u32;
+            let value = match self.cells.get_value((row, col)) {
+                Some(Data::Error(e)) => {
+                    return Some(Err(anyhow!("Cell {}{} contains the error {}", column_name(col), row + 1, e)))
+                }
+                Some(cell) => cell_to_bson(cell),
+                None => Bson::Null,
+            };
+            doc.insert(header.clone(), value);
+        }
+        Some(Ok(RawRecord::Document(doc)))
+    }
+
+    /// `line` is the spreadsheet row number of the last row read.
+    fn end(&self) -> RowEnd {
+        RowEnd {
+            line: self.next_row as u64,
+            offset: self.read,
+        }
+    }
+}
+
+/// Spreadsheet values keep their type: text stays text (with its leading
+/// zeros), dates become dates. Excel stores every number as a float, so
+/// whole numbers are read as integers.
+fn cell_to_bson(cell: &Data) -> Bson {
+    match cell {
+        Data::Empty => Bson::Null,
+        Data::String(s) => Bson::String(s.clone()),
+        Data::Bool(b) => Bson::Boolean(*b),
+        Data::Int(n) => Bson::Int64(*n),
+        Data::Float(f) if f.fract() == 0.0 && f.abs() < 9.0e15 => Bson::Int64(*f as i64),
+        Data::Float(f) => Bson::Double(*f),
+        Data::DateTime(dt) if dt.is_duration() => Bson::String(cell.to_string()),
+        Data::DateTime(dt) => match dt.as_datetime() {
+            Some(naive) => Bson::DateTime(DateTime::from_chrono(naive.and_utc())),
+            None => Bson::Double(dt.as_f64()),
+        },
+        Data::DateTimeIso(text) => parse_iso(text)
+            .map(|naive| Bson::DateTime(DateTime::from_chrono(naive.and_utc())))
+            .unwrap_or_else(|| Bson::String(text.clone())),
+        Data::DurationIso(text) => Bson::String(text.clone()),
+        Data::Error(e) => Bson::String(e.to_string()),
+    }
+}
+
+/// OpenDocument dates are stored as ISO 8601 text.
+fn parse_iso(text: &str) -> Option<NaiveDateTime> {
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(text) {
+        return Some(dt.naive_utc());
+    }
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().map(|d| d.and_time(chrono::NaiveTime::MIN)))
+}
+
+/// A zero-based (row, column) position.
+type Cell = (u32, u32);
+
+/// Parses `B3:H200` or `B3` into its first cell and, if given, its last.
+fn parse_range(range: &str) -> Result<(Cell, Option<Cell>)> {
+    let (start, end) = match range.split_once(':') {
+        Some((start, end)) => (start, Some(end)),
+        None => (range, None),
+    };
+    let (top, left) = parse_cell(start)?;
+    let last = match end {
+        Some(end) => {
+            let (bottom, right) = parse_cell(end)?;
+            if bottom < top || right < left {
+                return Err(anyhow!("Invalid cell range '{}': the end is before the start", range));
+            }
+            Some((bottom, right))
+        }
+        None => None,
+    };
+    Ok(((top, left), last))
+}
+
+fn parse_cell(cell: &str) -> Result<Cell> {
+    let invalid = || anyhow!("Invalid cell '{}', expected a reference like B3", cell);
+    let cell = cell.trim().replace('$', "");
+    let split = cell.find(|c: char| c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (letters, digits) = cell.split_at(split);
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(invalid());
+    }
+    let col = letters
+        .to_ascii_uppercase()
+        .bytes()
+        .try_fold(0u32, |col, b| col.checked_mul(26)?.checked_add((b - b'A' + 1) as u32))
+        .ok_or_else(invalid)?;
+    let row: u32 = digits.parse().map_err(|_| invalid())?;
+    if row == 0 {
+        return Err(invalid());
+    }
+    Ok((row - 1, col - 1))
+}
+
+/// Spreadsheet column letters for a zero-based column, e.g. 27 is `AB`.
+fn column_name(col: u32) -> String {
+    let mut name = Vec::new();
+    let mut n = col + 1;
+    while n > 0 {
+        name.push(b'A' + ((n - 1) % 26) as u8);
+        n = (n - 1) / 26;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cell_references_parse_to_zero_based_positions() {
+        assert_eq!(parse_cell("B3").unwrap(), (2, 1));
+        assert_eq!(parse_cell("$AB$10").unwrap(), (9, 27));
+        assert!(parse_cell("A0").is_err());
+        assert!(parse_cell("ZZZZZZZZZZZZ1").is_err());
+    }
+}
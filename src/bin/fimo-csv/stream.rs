@@ -0,0 +1,236 @@
+// src/stream.rs
+use crate::encoding::{bom_length, detect, Utf8Reader};
+use anyhow::{anyhow, Result};
+use encoding_rs::{Encoding, UTF_8};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+
+/// Read buffer size for inputs that are not sniffed.
+pub const READ_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Compression of an input, recognised by its first bytes or, failing that,
+/// by its file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+const EXTENSIONS: [(&str, Compression); 5] = [
+    (".gz", Compression::Gzip),
+    (".zst", Compression::Zstd),
+    (".zstd", Compression::Zstd),
+    (".bz2", Compression::Bzip2),
+    (".xz", Compression::Xz),
+];
+
+impl Compression {
+    fn detect(path: &str, head: &[u8]) -> Compression {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if head.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else if head.is_empty() {
+            let lower = path.to_lowercase();
+            EXTENSIONS
+                .iter()
+                .find(|(ext, _)| lower.ends_with(ext))
+                .map(|(_, compression)| *compression)
+                .unwrap_or(Compression::None)
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// The file name without a compression extension, e.g. `data.csv` for
+/// `data.csv.gz`, so the input format can be told from what is left.
+pub fn strip_compression(path: &str) -> &str {
+    let lower = path.to_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(ext, _)| lower.ends_with(ext))
+        .map(|(ext, _)| &path[..path.len() - ext.len()])
+        .unwrap_or(path)
+}
+
+/// Whether `path` names standard input.
+pub fn is_stdin(path: &str) -> bool {
+    path == "-"
+}
+
+/// An opened input. Plain files can be seeked; stdin, compressed and
+/// transcoded input can only be read from the start.
+pub enum InputStream {
+    File(BufReader<File>),
+    Stream(Box<dyn BufRead + Send>),
+}
+
+impl InputStream {
+    /// Opens a text input: a file, or stdin for `-`, decompressed when needed
+    /// and transcoded to UTF-8 without its BOM. `encoding` is detected from
+    /// the first bytes when `None`. `capacity` is the size of the read buffer.
+    pub fn open(path: &str, capacity: usize, encoding: Option<&'static Encoding>) -> Result<InputStream> {
+        let mut stream = InputStream::decompressed(path, capacity)?;
+        let head = match &mut stream {
+            InputStream::File(file) => file.fill_buf()?,
+            InputStream::Stream(stream) => stream.fill_buf()?,
+        };
+        let (encoding, bom) = match encoding {
+            Some(encoding) => (encoding, bom_length(head, encoding)),
+            None => detect(head),
+        };
+        if encoding == UTF_8 && bom == 0 {
+            return Ok(stream);
+        }
+
+        let mut reader = stream.into_reader();
+        if encoding == UTF_8 {
+            reader.consume(bom);
+            return Ok(InputStream::Stream(reader));
+        }
+        Ok(InputStream::Stream(Box::new(Utf8Reader::new(reader, encoding, capacity))))
+    }
+
+    /// Opens a file, or stdin for `-`, and decompresses it when needed.
+    fn decompressed(path: &str, capacity: usize) -> Result<InputStream> {
+        let mut raw: Box<dyn BufRead + Send> = if is_stdin(path) {
+            Box::new(BufReader::with_capacity(capacity, io::stdin()))
+        } else {
+            let mut file = BufReader::with_capacity(capacity, File::open(path)?);
+            if Compression::detect(path, file.fill_buf()?) == Compression::None {
+                return Ok(InputStream::File(file));
+            }
+            Box::new(file)
+        };
+
+        let decoded: Box<dyn BufRead + Send> = match Compression::detect(path, raw.fill_buf()?) {
+            Compression::None => return Ok(InputStream::Stream(raw)),
+            Compression::Gzip => Box::new(BufReader::with_capacity(capacity, flate2::bufread::MultiGzDecoder::new(raw))),
+            Compression::Zstd => Box::new(BufReader::with_capacity(capacity, zstd::stream::read::Decoder::with_buffer(raw)?)),
+            Compression::Bzip2 => Box::new(BufReader::with_capacity(capacity, bzip2::bufread::MultiBzDecoder::new(raw))),
+            Compression::Xz => Box::new(BufReader::with_capacity(
+                capacity,
+                xz2::bufread::XzDecoder::new_multi_decoder(raw),
+            )),
+        };
+        Ok(InputStream::Stream(decoded))
+    }
+
+    fn into_reader(self) -> Box<dyn BufRead + Send> {
+        match self {
+            InputStream::File(file) => Box::new(file),
+            InputStream::Stream(stream) => stream,
+        }
+    }
+
+    /// The input positioned at byte `offset` of its (decoded) content.
+    /// Streams are read up to the offset and the bytes before it dropped.
+    pub fn starting_at(self, offset: u64) -> Result<Box<dyn BufRead + Send>> {
+        match self {
+            InputStream::File(mut file) => {
+                file.seek(SeekFrom::Start(offset))?;
+                Ok(Box::new(file))
+            }
+            InputStream::Stream(mut stream) => {
+                let skipped = io::copy(&mut stream.by_ref().take(offset), &mut io::sink())?;
+                if skipped < offset {
+                    return Err(anyhow!("Input ends before the checkpointed offset {}", offset));
+                }
+                Ok(stream)
+            }
+        }
+    }
+}
+
+/// Opens an input that must be a plain file, such as Parquet or a workbook.
+pub fn open_plain_file(path: &str, format: &str) -> Result<File> {
+    if is_stdin(path) {
+        return Err(anyhow!("{} input cannot be read from stdin", format));
+    }
+    match InputStream::decompressed(path, READ_BUFFER_BYTES)? {
+        InputStream::File(_) => Ok(File::open(path)?),
+        InputStream::Stream(_) => Err(anyhow!("{} input cannot be compressed: {}", format, path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_from(path: &str, offset: u64) -> Result<String> {
+        let mut text = String::new();
+        InputStream::open(path, READ_BUFFER_BYTES, None)?.starting_at(offset)?.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    #[test]
+    fn magic_bytes_win_over_the_extension() {
+        assert_eq!(Compression::detect("rows.csv", &[0x1f, 0x8b, 0x08]), Compression::Gzip);
+        assert_eq!(Compression::detect("rows.csv", &[0x28, 0xb5, 0x2f, 0xfd, 0x00]), Compression::Zstd);
+        assert_eq!(Compression::detect("rows.csv", b"BZh91AY"), Compression::Bzip2);
+        assert_eq!(Compression::detect("rows.csv", b"\xfd7zXZ\x00\x00"), Compression::Xz);
+        assert_eq!(Compression::detect("rows.csv.gz", b"id,name\n"), Compression::None);
+        // An empty file has no magic bytes, so only the extension is left.
+        assert_eq!(Compression::detect("rows.csv.ZST", b""), Compression::Zstd);
+        assert_eq!(Compression::detect("rows.csv", b""), Compression::None);
+        assert_eq!(strip_compression("rows.CSV.Gz"), "rows.CSV");
+        assert_eq!(strip_compression("rows.csv"), "rows.csv");
+    }
+
+    #[test]
+    fn compressed_inputs_read_like_the_plain_file() {
+        let plain = read_from("tests/data/nulls.csv", 0).unwrap();
+        assert!(plain.starts_with("id,name,age,score\n"));
+        assert_eq!(read_from("tests/data/nulls.csv.gz", 0).unwrap(), plain);
+        assert_eq!(read_from("tests/data/nulls.csv.zst", 0).unwrap(), plain);
+        assert!(matches!(
+            InputStream::open("tests/data/nulls.csv.gz", READ_BUFFER_BYTES, None).unwrap(),
+            InputStream::Stream(_)
+        ));
+    }
+
+    #[test]
+    fn plain_files_seek_and_streams_skip_to_the_offset() {
+        let offset = "id,name,age,score\n".len() as u64;
+        assert!(matches!(
+            InputStream::open("tests/data/nulls.csv", READ_BUFFER_BYTES, None).unwrap(),
+            InputStream::File(_)
+        ));
+        let rest = read_from("tests/data/nulls.csv", offset).unwrap();
+        assert_eq!(rest, "1,Bob,,\\N\n2,NULL,31,4.5\n");
+        assert_eq!(read_from("tests/data/nulls.csv.gz", offset).unwrap(), rest);
+        assert_eq!(read_from("tests/data/nulls.csv.zst", offset).unwrap(), rest);
+    }
+
+    #[test]
+    fn streams_ending_before_the_offset_are_an_error() {
+        let error = read_from("tests/data/nulls.csv.gz", 1000).unwrap_err().to_string();
+        assert!(error.contains("before the checkpointed offset 1000"), "{}", error);
+        // A seek past the end of a plain file just reads nothing.
+        assert_eq!(read_from("tests/data/nulls.csv", 1000).unwrap(), "");
+    }
+
+    #[test]
+    fn transcoded_offsets_count_utf8_bytes_after_the_bom() {
+        let text = read_from("tests/data/utf16.csv", 0).unwrap();
+        assert!(!text.starts_with('\u{feff}'));
+        let header = text.find('\n').unwrap() + 1;
+        assert_eq!(read_from("tests/data/utf16.csv", header as u64).unwrap(), text[header..]);
+    }
+
+    #[test]
+    fn plain_files_only_accept_uncompressed_paths() {
+        assert!(open_plain_file("tests/data/people.parquet", "Parquet").is_ok());
+        let error = open_plain_file("tests/data/nulls.csv.gz", "Parquet").unwrap_err().to_string();
+        assert!(error.contains("cannot be compressed"), "{}", error);
+        assert!(open_plain_file("-", "Parquet").unwrap_err().to_string().contains("stdin"));
+    }
+}
@@ -1,11 +1,23 @@
 // src/template.rs
-use minijinja::Environment;
-use anyhow::Result;
+use minijinja::value::{Rest, Value, ValueKind};
+use minijinja::{Environment, Error, ErrorKind};
+use anyhow::{anyhow, Result};
+use bson::{oid::ObjectId, Bson, DateTime, Decimal128, Uuid};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Lookup tables by name, each mapping a key to a JSON value.
+pub type Lookups = HashMap<String, HashMap<String, serde_json::Value>>;
 
 pub fn load_templates<P: AsRef<Path>>(dir: P) -> Result<Environment<'static>> {
-    let mut env = Environment::new();
+    let lookups_dir = dir.as_ref().join("lookups");
+    let lookups = if lookups_dir.is_dir() { load_lookups(&lookups_dir)? } else { Lookups::new() };
+    let mut env = template_environment(lookups);
 
     for entry in fs::read_dir(dir)? {
         let entry = entry?;
@@ -21,4 +33,230 @@ pub fn load_templates<P: AsRef<Path>>(dir: P) -> Result<Environment<'static>> {
     }
 
     Ok(env)
-}
\ No newline at end of file
+}
+
+/// An environment with the BSON-aware helpers registered. Filters that build
+/// BSON values (`oid`, `date`, `decimal`, `now()`, `uuid()`) and `tojson`
+/// output Extended JSON that can be placed in a template as it is.
+pub fn template_environment(lookups: Lookups) -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_filter("tojson", tojson);
+    env.add_filter("oid", oid);
+    env.add_filter("date", date);
+    env.add_filter("decimal", decimal);
+    env.add_filter("hash", hash);
+    env.add_filter("slugify", slugify);
+    env.add_filter("split", split);
+    env.add_function("now", || extended_json(Bson::DateTime(DateTime::now())));
+    env.add_function("uuid", || extended_json(Bson::from(Uuid::new())));
+    env.add_function("coalesce", coalesce);
+
+    let lookups = Arc::new(lookups);
+    env.add_function("lookup", move |table: String, key: Value, default: Option<Value>| {
+        let entries = lookups
+            .get(&table)
+            .ok_or_else(|| invalid(format!("unknown lookup table '{}'", table)))?;
+        let key = key.as_str().map(str::to_string).unwrap_or_else(|| key.to_string());
+        Ok(match entries.get(&key) {
+            Some(found) => Value::from_serialize(found),
+            None => default.unwrap_or(Value::from(())),
+        })
+    });
+    env
+}
+
+/// Reads `<name>.json` (an object) and `<name>.csv` (key and value columns
+/// after a header row) files into lookup tables called `<name>`.
+fn load_lookups(dir: &Path) -> Result<Lookups> {
+    let mut lookups = Lookups::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+            continue;
+        };
+        let table = match path.extension().and_then(|s| s.to_str()) {
+            Some("json") => match serde_json::from_str(&fs::read_to_string(&path)?)? {
+                serde_json::Value::Object(map) => map.into_iter().collect(),
+                _ => return Err(anyhow!("Lookup table {} must be a JSON object", path.display())),
+            },
+            Some("csv") => {
+                let mut rdr = csv::Reader::from_path(&path)?;
+                let mut table = HashMap::new();
+                for record in rdr.records() {
+                    let record = record?;
+                    let value = record.get(1).unwrap_or_default();
+                    table.insert(record.get(0).unwrap_or_default().to_string(), serde_json::Value::from(value));
+                }
+                table
+            }
+            _ => continue,
+        };
+        lookups.insert(name, table);
+    }
+    Ok(lookups)
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidOperation, message)
+}
+
+/// A BSON value as relaxed Extended JSON, marked safe so it is printed as is.
+fn extended_json(value: Bson) -> Value {
+    Value::from_safe_string(value.into_relaxed_extjson().to_string())
+}
+
+/// The text of a value, or of the `wrapper` key of an Extended JSON object
+/// such as `{"$oid": "..."}`.
+fn text_of(value: &Value, wrapper: &str) -> Option<String> {
+    match value.kind() {
+        ValueKind::String => value.as_str().map(str::to_string),
+        ValueKind::Number => Some(value.to_string()),
+        // Dates outside 1970-9999 are written as `{"$date": {"$numberLong": ...}}`.
+        ValueKind::Map => value.get_attr(wrapper).ok().and_then(|inner| match inner.kind() {
+            ValueKind::Map => text_of(&inner, "$numberLong"),
+            _ => text_of(&inner, wrapper),
+        }),
+        _ => None,
+    }
+}
+
+/// Serializes any value to JSON, escaping strings properly:
+/// `"name": {{ row.name | tojson }}`.
+fn tojson(value: Value) -> Result<Value, Error> {
+    serde_json::to_string(&value)
+        .map(Value::from_safe_string)
+        .map_err(|e| invalid(format!("cannot serialize to JSON: {}", e)))
+}
+
+/// `{"$oid": ...}` from a 24-digit hex string.
+fn oid(value: Value) -> Result<Value, Error> {
+    let text = text_of(&value, "$oid").ok_or_else(|| invalid(format!("cannot make an ObjectId from {}", value)))?;
+    ObjectId::parse_str(&text)
+        .map(|id| extended_json(Bson::ObjectId(id)))
+        .map_err(|_| invalid(format!("'{}' is not a valid ObjectId", text)))
+}
+
+/// `{"$date": ...}` from RFC 3339 text, text in the given chrono `format`
+/// (read as UTC), or milliseconds since the epoch.
+fn date(value: Value, format: Option<String>) -> Result<Value, Error> {
+    if let Ok(millis) = i64::try_from(value.clone()) {
+        return Ok(extended_json(Bson::DateTime(DateTime::from_millis(millis))));
+    }
+    let text = text_of(&value, "$date").ok_or_else(|| invalid(format!("cannot make a date from {}", value)))?;
+    // A value that already is a date ignores the format.
+    let format = format.filter(|_| value.kind() != ValueKind::Map);
+    let parsed = match &format {
+        _ if value.kind() == ValueKind::Map && text.parse::<i64>().is_ok() => text.parse().ok().map(DateTime::from_millis),
+        Some(format) => NaiveDateTime::parse_from_str(&text, format)
+            .or_else(|_| NaiveDate::parse_from_str(&text, format).map(|d| d.and_time(NaiveTime::MIN)))
+            .map(|naive| DateTime::from_chrono(Utc.from_utc_datetime(&naive)))
+            .ok(),
+        None => DateTime::parse_rfc3339_str(&text).ok(),
+    };
+    parsed
+        .map(|date| extended_json(Bson::DateTime(date)))
+        .ok_or_else(|| invalid(format!("cannot parse '{}' as a date", text)))
+}
+
+/// `{"$numberDecimal": ...}` from a number or numeric text.
+fn decimal(value: Value) -> Result<Value, Error> {
+    let text = text_of(&value, "$numberDecimal").ok_or_else(|| invalid(format!("cannot make a decimal from {}", value)))?;
+    Decimal128::from_str(&text)
+        .map(|d| extended_json(Bson::Decimal128(d)))
+        .map_err(|_| invalid(format!("'{}' is not a valid decimal", text)))
+}
+
+/// Hex digest of a value: `sha256` (the default) or `sha512`.
+fn hash(value: Value, algorithm: Option<String>) -> Result<String, Error> {
+    let text = value.as_str().map(str::to_string).unwrap_or_else(|| value.to_string());
+    let digest = match algorithm.as_deref().unwrap_or("sha256") {
+        "sha256" => Sha256::digest(text.as_bytes()).to_vec(),
+        "sha512" => Sha512::digest(text.as_bytes()).to_vec(),
+        other => return Err(invalid(format!("unsupported hash algorithm '{}'", other))),
+    };
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Lowercase words joined by `-`: `Zoë Müller & Co.` becomes `zoë-müller-co`.
+fn slugify(value: String) -> String {
+    let mut slug = String::new();
+    for c in value.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Splits text on `separator` (default `,`) into trimmed parts.
+fn split(value: String, separator: Option<String>) -> Vec<String> {
+    let separator = separator.unwrap_or_else(|| ",".to_string());
+    if value.is_empty() {
+        return Vec::new();
+    }
+    value.split(separator.as_str()).map(|part| part.trim().to_string()).collect()
+}
+
+/// The first argument that is neither undefined, none nor an empty string.
+fn coalesce(values: Rest<Value>) -> Value {
+    values
+        .0
+        .into_iter()
+        .find(|v| !v.is_undefined() && !v.is_none() && v.as_str() != Some(""))
+        .unwrap_or(Value::from(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minijinja::context;
+
+    fn render(source: &str) -> Result<String, Error> {
+        let mut countries = HashMap::new();
+        countries.insert("FR".to_string(), serde_json::json!("France"));
+        countries.insert("1".to_string(), serde_json::json!({ "code": 1 }));
+        let lookups = Lookups::from([("countries".to_string(), countries)]);
+        template_environment(lookups).render_str(source, context! { name => "Zoë Müller & Co.", empty => "" })
+    }
+
+    #[test]
+    fn filters_write_extended_json() {
+        assert_eq!(
+            render("{{ '507f1f77bcf86cd799439011' | oid }}").unwrap(),
+            r#"{"$oid":"507f1f77bcf86cd799439011"}"#
+        );
+        assert_eq!(render("{{ 0 | date }}").unwrap(), r#"{"$date":"1970-01-01T00:00:00Z"}"#);
+        assert_eq!(
+            render("{{ '15/01/1990' | date('%d/%m/%Y') }}").unwrap(),
+            r#"{"$date":"1990-01-15T00:00:00Z"}"#
+        );
+        assert_eq!(render("{{ '1.50' | decimal }}").unwrap(), r#"{"$numberDecimal":"1.50"}"#);
+        assert_eq!(render("{{ name | tojson }}").unwrap(), r#""Zoë Müller & Co.""#);
+        assert!(render("{{ 'xyz' | oid }}").is_err());
+        assert!(render("{{ 'soon' | date }}").is_err());
+    }
+
+    #[test]
+    fn text_helpers() {
+        assert_eq!(render("{{ name | slugify }}").unwrap(), "zoë-müller-co");
+        assert_eq!(render("{{ 'a, b,c' | split | join('|') }}").unwrap(), "a|b|c");
+        assert_eq!(render("{{ '' | split | length }}").unwrap(), "0");
+        assert_eq!(render("{{ coalesce(missing, none, empty, 'x') }}").unwrap(), "x");
+        assert_eq!(
+            render("{{ 'abc' | hash }}").unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(render("{{ 'abc' | hash('md5') }}").is_err());
+    }
+
+    #[test]
+    fn lookups_find_keys_or_fall_back() {
+        assert_eq!(render("{{ lookup('countries', 'FR') }}").unwrap(), "France");
+        assert_eq!(render("{{ lookup('countries', 1).code }}").unwrap(), "1");
+        assert_eq!(render("{{ lookup('countries', 'XX', 'Unknown') }}").unwrap(), "Unknown");
+        assert_eq!(render("{{ lookup('countries', 'XX') is none }}").unwrap(), "true");
+        assert!(render("{{ lookup('tiers', 'a') }}").is_err());
+    }
+}
@@ -1,12 +1,14 @@
 // src/transform.rs
-use crate::mapping::FieldMapping;
-use bson::{Bson, DateTime, Decimal128, Document, oid::ObjectId, Regex, Timestamp};
+use crate::mapping::{scalar_text, DefaultValue, FieldDef, FieldMapping, OnEmpty};
+use crate::path::{fill_gaps, get_path, insert_path, parse_path, take_path, PathSegment};
+use bson::{Bson, DateTime, Decimal128, Document, oid::ObjectId, Regex, Timestamp, Uuid};
 use serde_json::Value;
 use anyhow::{anyhow, Result};
 use minijinja::{Environment, context};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::str::FromStr;
-use chrono::{NaiveDateTime, TimeZone, Utc};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
 
 pub fn validate_required_fields(record: &HashMap<String, String>, mapping: &FieldMapping) -> Result<()> {
     for (key, field_def) in &mapping.0 {
@@ -23,93 +25,614 @@ pub fn apply_mapping(record: &HashMap<String, String>, mapping: &FieldMapping, r
     for (key, value) in record {
         let field_def = mapping.0.get(key);
 
-        let bson_value = if let Some(def) = field_def {
-            match def.r#type.as_str() {
-                "string" => Bson::String(value.to_string()),
-                "int" => value.parse::<i32>().map(Bson::Int32)
-                    .map_err(|_| anyhow!("Row {}: Failed to convert '{}' to int for field '{}'", row_num, value, key))?,
-                "long" => value.parse::<i64>().map(Bson::Int64)
-                    .map_err(|_| anyhow!("Row {}: Failed to convert '{}' to long for field '{}'", row_num, value, key))?,
-                "double" => value.parse::<f64>().map(Bson::Double)
-                    .map_err(|_| anyhow!("Row {}: Failed to convert '{}' to double for field '{}'", row_num, value, key))?,
-                "decimal" => Decimal128::from_str(value)
-                    .map(Bson::Decimal128)
-                    .map_err(|_| anyhow!("Row {}: Failed to convert '{}' to decimal128 for field '{}'", row_num, value, key))?,
-                "bool" => {
-                    let is_true = def.truthy.as_ref()
-                        .map(|list| list.iter().any(|t| t.eq_ignore_ascii_case(value)))
-                        .unwrap_or_else(|| matches!(value.to_lowercase().as_str(), "true" | "t" | "yes" | "1" | "y"));
-
-                    let is_false = def.falsy.as_ref()
-                        .map(|list| list.iter().any(|f| f.eq_ignore_ascii_case(value)))
-                        .unwrap_or_else(|| matches!(value.to_lowercase().as_str(), "false" | "f" | "no" | "0" | "n"));
-
-                    if is_true {
-                        Bson::Boolean(true)
-                    } else if is_false {
-                        Bson::Boolean(false)
-                    } else {
-                        return Err(anyhow!("Row {}: Invalid value '{}' for bool field '{}'", row_num, value, key));
-                    }
-                },
-                "objectId" => ObjectId::parse_str(value)
-                    .map(Bson::ObjectId)
-                    .map_err(|_| anyhow!("Row {}: Failed to parse '{}' as ObjectId for field '{}'", row_num, value, key))?,
-                "date" => {
-                    if let Some(formats) = &def.formats {
-                        let mut parsed = None;
-                        for fmt in formats {
-                            if let Ok(ndt) = NaiveDateTime::parse_from_str(value, fmt) {
-                                parsed = Some(Bson::DateTime(DateTime::from_chrono(Utc.from_utc_datetime(&ndt))));
-                                break;
-                            }
-                        }
-                        if let Some(date) = parsed {
-                            date
-                        } else {
-                            return Err(anyhow!("Row {}: Could not parse '{}' with any format for field '{}'", row_num, value, key));
-                        }
-                    } else {
-                        DateTime::parse_rfc3339_str(value)
-                            .map(Bson::DateTime)
-                            .map_err(|_| anyhow!("Row {}: Failed to parse '{}' as ISODate for field '{}'", row_num, value, key))?
+        let bson_value = match field_def {
+            Some(def) if def.is_null_value(value) => match def.on_empty() {
+                OnEmpty::Keep => convert_value(value, def, key, row_num)?,
+                OnEmpty::Omit => continue,
+                OnEmpty::Null => Bson::Null,
+                OnEmpty::Default => default_value(def, key, row_num)?,
+            },
+            Some(def) => convert_value(value, def, key, row_num)?,
+            None => Bson::String(value.to_string()),
+        };
+
+        insert_mapped(&mut doc, key, field_def, bson_value, row_num)?;
+    }
+
+    // Fields absent from the input still get their default.
+    for (key, def) in &mapping.0 {
+        if !record.contains_key(key) && def.on_empty() == OnEmpty::Default {
+            let bson_value = default_value(def, key, row_num)?;
+            insert_mapped(&mut doc, key, Some(def), bson_value, row_num)?;
+        }
+    }
+
+    fill_gaps(&mut doc);
+    Ok(doc)
+}
+
+fn insert_mapped(doc: &mut Document, key: &str, field_def: Option<&FieldDef>, value: Bson, row_num: usize) -> Result<()> {
+    insert_at(doc, key, field_def, vec![PathSegment::Key(key.to_string())], value, row_num)
+}
+
+/// Inserts a mapped value at its `target`, or at `source` when it has none.
+fn insert_at(
+    doc: &mut Document,
+    key: &str,
+    field_def: Option<&FieldDef>,
+    source: Vec<PathSegment>,
+    value: Bson,
+    row_num: usize,
+) -> Result<()> {
+    let target = field_def.and_then(|def| def.target.as_deref());
+    let segments = match field_def.and_then(FieldDef::target_path) {
+        Some(path) => path.map_err(|e| anyhow!("Row {}: {}", row_num, e))?,
+        None => &source,
+    };
+    insert_path(doc, segments, value).map_err(|e| {
+        anyhow!("Row {}: Cannot map field '{}' to '{}': {}", row_num, key, target.unwrap_or(key), e)
+    })
+}
+
+/// Checks required fields of a record that already has typed values, such
+/// as a JSON object. Mapping keys may be dot paths into nested documents.
+pub fn validate_required_document(record: &Document, mapping: &FieldMapping) -> Result<()> {
+    for (key, field_def) in &mapping.0 {
+        if field_def.required && get_path(record, &field_path(record, key)).is_none() {
+            return Err(anyhow!("Missing required field: {}", key));
+        }
+    }
+    Ok(())
+}
+
+/// Like `apply_mapping`, for a record that already has typed values. Each
+/// mapped value is converted in place or moved to its `target`; unmapped
+/// values are kept as they are.
+pub fn apply_mapping_document(mut record: Document, mapping: &FieldMapping, row_num: usize) -> Result<Document> {
+    let mut mapped = Vec::new();
+
+    for (key, def) in &mapping.0 {
+        let source = field_path(&record, key);
+        let bson_value = match take_path(&mut record, &source) {
+            Some(Bson::String(value)) if def.is_null_value(&value) => match def.on_empty() {
+                OnEmpty::Keep => convert_value(&value, def, key, row_num)?,
+                OnEmpty::Omit => continue,
+                OnEmpty::Null => Bson::Null,
+                OnEmpty::Default => default_value(def, key, row_num)?,
+            },
+            Some(Bson::Null) => match def.on_empty() {
+                OnEmpty::Keep | OnEmpty::Null => Bson::Null,
+                OnEmpty::Omit => continue,
+                OnEmpty::Default => default_value(def, key, row_num)?,
+            },
+            Some(value) => convert_typed(value, def, key, row_num)?,
+            None if def.on_empty() == OnEmpty::Default => default_value(def, key, row_num)?,
+            None => continue,
+        };
+        mapped.push((key, def, source, bson_value));
+    }
+
+    for (key, def, source, bson_value) in mapped {
+        insert_at(&mut record, key, Some(def), source, bson_value, row_num)?;
+    }
+
+    fill_gaps(&mut record);
+    Ok(record)
+}
+
+/// A mapping key names a top-level field if the record has one by that
+/// name, otherwise it is read as a path like `address.zip`.
+fn field_path(record: &Document, key: &str) -> Vec<PathSegment> {
+    if record.contains_key(key) {
+        return vec![PathSegment::Key(key.to_string())];
+    }
+    parse_path(key).unwrap_or_else(|_| vec![PathSegment::Key(key.to_string())])
+}
+
+/// Converts a typed value. Strings are converted like CSV fields, values
+/// that already have the field's type are kept, and other scalars are
+/// converted from their text form.
+pub fn convert_typed(value: Bson, def: &FieldDef, key: &str, row_num: usize) -> Result<Bson> {
+    if def.r#type != "array" {
+        return convert_typed_scalar(value, &def.r#type, def, key, row_num);
+    }
+
+    let items = match value {
+        Bson::String(text) => return convert_value(&text, def, key, row_num),
+        Bson::Array(items) => items,
+        other => vec![other],
+    };
+    let element_type = def.element_type.as_deref().unwrap_or("string");
+    let mut converted = Vec::new();
+    for item in items {
+        let item = convert_typed_scalar(item, element_type, def, key, row_num)?;
+        if def.unique && converted.contains(&item) {
+            continue;
+        }
+        converted.push(item);
+    }
+    Ok(Bson::Array(converted))
+}
+
+fn convert_typed_scalar(value: Bson, field_type: &str, def: &FieldDef, key: &str, row_num: usize) -> Result<Bson> {
+    let raw = bson_text(&value);
+    let converted = match (&value, raw.as_deref()) {
+        (Bson::String(_), Some(text)) => convert_scalar(text, field_type, def, key, row_num)?,
+        _ if has_type(&value, field_type) => value.clone(),
+        (_, Some(text)) => convert_scalar(text, field_type, def, key, row_num)?,
+        (_, None) => {
+            return Err(anyhow!(
+                "Row {}: Cannot convert {:?} value to {} for field '{}'",
+                row_num,
+                value.element_type(),
+                field_type,
+                key
+            ))
+        }
+    };
+    check_constraints(raw.as_deref().unwrap_or_default(), &converted, field_type, def, key, row_num)?;
+    Ok(converted)
+}
+
+fn has_type(value: &Bson, field_type: &str) -> bool {
+    matches!(
+        (value, field_type),
+        (Bson::String(_), "string")
+            | (Bson::Int32(_), "int")
+            | (Bson::Int64(_), "long")
+            | (Bson::Double(_), "double")
+            | (Bson::Decimal128(_), "decimal")
+            | (Bson::Boolean(_), "bool")
+            | (Bson::ObjectId(_), "objectId")
+            | (Bson::DateTime(_), "date")
+            | (Bson::Timestamp(_), "timestamp")
+            | (Bson::RegularExpression(_), "regex")
+    )
+}
+
+/// The text form of a scalar value, as it would appear in a CSV field.
+fn bson_text(value: &Bson) -> Option<String> {
+    match value {
+        Bson::String(s) => Some(s.clone()),
+        Bson::Int32(n) => Some(n.to_string()),
+        Bson::Int64(n) => Some(n.to_string()),
+        Bson::Double(n) => Some(n.to_string()),
+        Bson::Decimal128(n) => Some(n.to_string()),
+        Bson::Boolean(b) => Some(b.to_string()),
+        Bson::ObjectId(id) => Some(id.to_hex()),
+        Bson::DateTime(date) => date.try_to_rfc3339_string().ok(),
+        Bson::Timestamp(ts) => Some(ts.time.to_string()),
+        Bson::RegularExpression(regex) => Some(regex.pattern.clone()),
+        _ => None,
+    }
+}
+
+fn default_value(def: &FieldDef, key: &str, row_num: usize) -> Result<Bson> {
+    match &def.default {
+        Some(DefaultValue::Generate { generate }) => {
+            let as_string = def.r#type == "string";
+            match generate.as_str() {
+                "now" if as_string => Ok(Bson::String(Utc::now().to_rfc3339())),
+                "now" => Ok(Bson::DateTime(DateTime::now())),
+                "objectId" if as_string => Ok(Bson::String(ObjectId::new().to_hex())),
+                "objectId" => Ok(Bson::ObjectId(ObjectId::new())),
+                "uuid" if as_string => Ok(Bson::String(Uuid::new().to_string())),
+                "uuid" => Ok(Bson::from(Uuid::new())),
+                other => Err(anyhow!("Row {}: Unknown default generator '{}' for field '{}'", row_num, other, key)),
+            }
+        }
+        Some(DefaultValue::Literal(serde_yaml::Value::Null)) => Ok(Bson::Null),
+        Some(DefaultValue::Literal(literal)) => match scalar_text(literal) {
+            Some(text) => convert_value(&text, def, key, row_num),
+            None => Err(anyhow!("Row {}: Default for field '{}' must be a scalar", row_num, key)),
+        },
+        None => Err(anyhow!("Row {}: Field '{}' is empty and has no default", row_num, key)),
+    }
+}
+
+pub fn convert_value(value: &str, def: &FieldDef, key: &str, row_num: usize) -> Result<Bson> {
+    if def.r#type != "array" {
+        let converted = convert_scalar(value, &def.r#type, def, key, row_num)?;
+        check_constraints(value, &converted, &def.r#type, def, key, row_num)?;
+        return Ok(converted);
+    }
+
+    let separator = def.separator.as_deref().unwrap_or(",");
+    let element_type = def.element_type.as_deref().unwrap_or("string");
+    if element_type == "array" {
+        return Err(anyhow!("Row {}: Nested arrays are not supported for field '{}'", row_num, key));
+    }
+
+    let mut items = Vec::new();
+    for part in value.split(separator) {
+        let part = if def.trim { part.trim() } else { part };
+        if part.is_empty() {
+            continue;
+        }
+        let item = convert_scalar(part, element_type, def, key, row_num)?;
+        check_constraints(part, &item, element_type, def, key, row_num)?;
+        if def.unique && items.contains(&item) {
+            continue;
+        }
+        items.push(item);
+    }
+    Ok(Bson::Array(items))
+}
+
+pub fn convert_scalar(value: &str, field_type: &str, def: &FieldDef, key: &str, row_num: usize) -> Result<Bson> {
+    let bson_value = match field_type {
+        "string" => Bson::String(value.to_string()),
+        "int" => value.parse::<i32>().map(Bson::Int32)
+            .map_err(|_| anyhow!("Row {}: Failed to convert '{}' to int for field '{}'", row_num, value, key))?,
+        "long" => value.parse::<i64>().map(Bson::Int64)
+            .map_err(|_| anyhow!("Row {}: Failed to convert '{}' to long for field '{}'", row_num, value, key))?,
+        "double" => value.parse::<f64>().map(Bson::Double)
+            .map_err(|_| anyhow!("Row {}: Failed to convert '{}' to double for field '{}'", row_num, value, key))?,
+        "decimal" => Decimal128::from_str(value)
+            .map(Bson::Decimal128)
+            .map_err(|_| anyhow!("Row {}: Failed to convert '{}' to decimal128 for field '{}'", row_num, value, key))?,
+        "bool" => {
+            let is_true = def.truthy.as_ref()
+                .map(|list| list.iter().any(|t| t.eq_ignore_ascii_case(value)))
+                .unwrap_or_else(|| matches!(value.to_lowercase().as_str(), "true" | "t" | "yes" | "1" | "y"));
+
+            let is_false = def.falsy.as_ref()
+                .map(|list| list.iter().any(|f| f.eq_ignore_ascii_case(value)))
+                .unwrap_or_else(|| matches!(value.to_lowercase().as_str(), "false" | "f" | "no" | "0" | "n"));
+
+            if is_true {
+                Bson::Boolean(true)
+            } else if is_false {
+                Bson::Boolean(false)
+            } else {
+                return Err(anyhow!("Row {}: Invalid value '{}' for bool field '{}'", row_num, value, key));
+            }
+        },
+        "objectId" => ObjectId::parse_str(value)
+            .map(Bson::ObjectId)
+            .map_err(|_| anyhow!("Row {}: Failed to parse '{}' as ObjectId for field '{}'", row_num, value, key))?,
+        "date" => {
+            if let Some(formats) = &def.formats {
+                let mut parsed = None;
+                for fmt in formats {
+                    // Date-only formats such as "%Y%m%d" are read as midnight.
+                    let ndt = NaiveDateTime::parse_from_str(value, fmt)
+                        .or_else(|_| NaiveDate::parse_from_str(value, fmt).map(|d| d.and_time(NaiveTime::MIN)));
+                    if let Ok(ndt) = ndt {
+                        parsed = Some(Bson::DateTime(DateTime::from_chrono(Utc.from_utc_datetime(&ndt))));
+                        break;
                     }
-                },
-                "timestamp" => {
-                    let ts = value.parse::<u32>()?;
-                    Bson::Timestamp(Timestamp { time: ts, increment: 1 })
-                },
-                "regex" => Bson::RegularExpression(Regex { pattern: value.clone(), options: "".to_string() }),
-                _ => Bson::String(value.to_string())
+                }
+                if let Some(date) = parsed {
+                    date
+                } else {
+                    return Err(anyhow!("Row {}: Could not parse '{}' with any format for field '{}'", row_num, value, key));
+                }
+            } else {
+                DateTime::parse_rfc3339_str(value)
+                    .map(Bson::DateTime)
+                    .map_err(|_| anyhow!("Row {}: Failed to parse '{}' as ISODate for field '{}'", row_num, value, key))?
+            }
+        },
+        "timestamp" => {
+            let ts = value.parse::<u32>()?;
+            Bson::Timestamp(Timestamp { time: ts, increment: 1 })
+        },
+        "regex" => Bson::RegularExpression(Regex { pattern: value.to_string(), options: "".to_string() }),
+        _ => Bson::String(value.to_string())
+    };
+    Ok(bson_value)
+}
+
+/// Enforces `enum`, `pattern`, `min`/`max` and `min_length`/`max_length` on
+/// a converted value. Array fields are checked element by element.
+fn check_constraints(raw: &str, value: &Bson, value_type: &str, def: &FieldDef, key: &str, row_num: usize) -> Result<()> {
+    if let Some(allowed) = &def.r#enum {
+        if !allowed.iter().any(|a| scalar_text(a).as_deref() == Some(raw)) {
+            let list: Vec<String> = allowed.iter().filter_map(scalar_text).collect();
+            return Err(anyhow!("Row {}: Value '{}' for field '{}' is not one of: {}", row_num, raw, key, list.join(", ")));
+        }
+    }
+
+    if let Some(regex) = def.pattern_regex() {
+        if !regex?.is_match(raw) {
+            return Err(anyhow!("Row {}: Value '{}' for field '{}' does not match pattern '{}'", row_num, raw, key, def.pattern.as_deref().unwrap_or_default()));
+        }
+    }
+
+    let length = raw.chars().count();
+    if let Some(min_length) = def.min_length {
+        if length < min_length {
+            return Err(anyhow!("Row {}: Value '{}' for field '{}' is shorter than {} characters", row_num, raw, key, min_length));
+        }
+    }
+    if let Some(max_length) = def.max_length {
+        if length > max_length {
+            return Err(anyhow!("Row {}: Value '{}' for field '{}' is longer than {} characters", row_num, raw, key, max_length));
+        }
+    }
+
+    for (bound, is_min) in [(&def.min, true), (&def.max, false)] {
+        let Some(bound_text) = bound.as_ref().and_then(scalar_text) else {
+            continue;
+        };
+        let ordering = match value {
+            Bson::DateTime(date) => {
+                let (min, max) = date_bounds(def, key, row_num)?;
+                let Some(limit) = (if is_min { min } else { max }) else {
+                    continue;
+                };
+                date.cmp(limit)
+            }
+            // Longs and decimals are compared exactly; a double would round them.
+            Bson::Int32(_) | Bson::Int64(_) | Bson::Decimal128(_) => {
+                let number = bson_text(value).unwrap_or_default();
+                let exact = match (number.parse::<i64>(), bound_text.trim().parse::<i64>()) {
+                    (Ok(number), Ok(limit)) => Some(number.cmp(&limit)),
+                    _ => compare_decimal(&number, bound_text.trim()),
+                };
+                let Some(ordering) = exact else {
+                    return Err(anyhow!("Row {}: Cannot compare '{}' for {} field '{}' against bound '{}'", row_num, raw, value_type, key, bound_text));
+                };
+                ordering
+            }
+            _ => {
+                let (Ok(number), Ok(limit)) = (raw.parse::<f64>(), bound_text.parse::<f64>()) else {
+                    return Err(anyhow!("Row {}: Cannot compare '{}' for {} field '{}' against bound '{}'", row_num, raw, value_type, key, bound_text));
+                };
+                number.total_cmp(&limit)
             }
-        } else {
-            Bson::String(value.to_string())
         };
+        if is_min && ordering == Ordering::Less {
+            return Err(anyhow!("Row {}: Value '{}' for field '{}' is below the minimum {}", row_num, raw, key, bound_text));
+        }
+        if !is_min && ordering == Ordering::Greater {
+            return Err(anyhow!("Row {}: Value '{}' for field '{}' is above the maximum {}", row_num, raw, key, bound_text));
+        }
+    }
+
+    Ok(())
+}
+
+/// The `min` and `max` of a date field, parsed like its values on first use.
+fn date_bounds<'a>(def: &'a FieldDef, key: &str, row_num: usize) -> Result<&'a (Option<DateTime>, Option<DateTime>)> {
+    if let Some(bounds) = def.date_bounds.get() {
+        return Ok(bounds);
+    }
+    let parse = |bound: &Option<serde_yaml::Value>| -> Result<Option<DateTime>> {
+        match bound.as_ref().and_then(scalar_text) {
+            Some(text) => match convert_scalar(&text, "date", def, key, row_num)? {
+                Bson::DateTime(date) => Ok(Some(date)),
+                _ => Ok(None),
+            },
+            None => Ok(None),
+        }
+    };
+    let bounds = (parse(&def.min)?, parse(&def.max)?);
+    Ok(def.date_bounds.get_or_init(|| bounds))
+}
+
+/// Compares two decimal numbers such as `-12.50` or `1.5E+3` without
+/// rounding. Returns `None` when either is not a finite decimal.
+fn compare_decimal(a: &str, b: &str) -> Option<Ordering> {
+    let (a_negative, a_digits, a_exponent) = parse_decimal(a)?;
+    let (b_negative, b_digits, b_exponent) = parse_decimal(b)?;
+    let ordering = match (a_digits.is_empty(), b_digits.is_empty()) {
+        (true, true) => return Some(Ordering::Equal),
+        (true, false) => return Some(if b_negative { Ordering::Greater } else { Ordering::Less }),
+        (false, true) => return Some(if a_negative { Ordering::Less } else { Ordering::Greater }),
+        (false, false) if a_negative != b_negative => return Some(if a_negative { Ordering::Less } else { Ordering::Greater }),
+        (false, false) => a_exponent.cmp(&b_exponent).then_with(|| a_digits.cmp(&b_digits)),
+    };
+    Some(if a_negative { ordering.reverse() } else { ordering })
+}
 
-        doc.insert(key, bson_value);
+/// A decimal as its sign, its significant digits and the exponent `e` so
+/// that the value is `0.<digits> * 10^e`. Zero has no digits.
+fn parse_decimal(text: &str) -> Option<(bool, Vec<u8>, i64)> {
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(at) => (&unsigned[..at], unsigned[at + 1..].parse::<i64>().ok()?),
+        None => (unsigned, 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    if !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) {
+        return None;
     }
 
-    Ok(doc)
+    let mut exponent = exponent.checked_add(whole.len() as i64)?;
+    let mut digits: Vec<u8> = whole.bytes().chain(fraction.bytes()).collect();
+    let leading = digits.iter().take_while(|&&d| d == b'0').count();
+    digits.drain(..leading);
+    exponent -= leading as i64;
+    while digits.last() == Some(&b'0') {
+        digits.pop();
+    }
+    Some((negative, digits, exponent))
+}
+
+/// Where a row came from, available to templates as `file`, `row_num`
+/// and `run_id`.
+pub struct RowOrigin<'a> {
+    pub file: &'a str,
+    pub row_num: usize,
+    pub run_id: &'a str,
+}
+
+/// Operations a row can be written with.
+pub const OPERATIONS: [&str; 7] = ["insert", "update", "upsert", "update_many", "replace", "delete", "delete_many"];
+
+/// Name under which `op_expr` is added to the template environment.
+pub const OP_EXPR_TEMPLATE: &str = "<op_expr>";
+
+/// How the operation, and so the template, of each row is chosen.
+#[derive(Clone)]
+pub enum Operation {
+    /// The same operation for every row (`--operation`).
+    Fixed(String),
+    /// The value of a column of the input row (`op_column`).
+    Column(String),
+    /// The output of the `op_expr` template.
+    Expr,
 }
 
+/// Renders a mapped row with the template of its operation, returning the
+/// operation and the rendered JSON. `op_value` is the row's value of the
+/// `op_column`; it and the output of `op_expr` are looked up in `op_map`.
 pub fn render_operation(
     env: &Environment<'_>,
-    operation: &str,
+    operation: &Operation,
+    op_map: &HashMap<String, String>,
     bson_doc: &Document,
+    op_value: Option<&str>,
     raw_insert: bool,
-) -> Result<Option<Value>> {
+    origin: &RowOrigin,
+) -> Result<Option<(String, Value)>> {
+    // Relaxed Extended JSON, so binary data survives the round trip.
+    let json = Bson::Document(bson_doc.clone()).into_relaxed_extjson();
+    let ctx = context! {
+        row => json,
+        file => origin.file,
+        row_num => origin.row_num,
+        run_id => origin.run_id,
+    };
+
+    let routed = match operation {
+        Operation::Fixed(name) => name.clone(),
+        Operation::Column(column) => match op_value.map(str::trim) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => return Err(anyhow!("No operation name in column '{}'", column)),
+        },
+        Operation::Expr => {
+            let name = env.get_template(OP_EXPR_TEMPLATE)?.render(ctx.clone())?;
+            match name.trim() {
+                "" => return Err(anyhow!("op_expr gave no operation")),
+                name => name.to_string(),
+            }
+        }
+    };
+    let operation = op_map.get(&routed).cloned().unwrap_or(routed);
+    // Other names pick a template that renders a list of operations.
+    if !OPERATIONS.contains(&operation.as_str()) && (raw_insert || env.get_template(&operation).is_err()) {
+        return Err(anyhow!("Unknown operation '{}'; add it to op_map to route it", operation));
+    }
     if raw_insert {
-        let json = serde_json::to_value(bson_doc)?;
-        return Ok(Some(json));
+        return Ok(Some((operation, json)));
     }
 
-    if let Some(tmpl) = env.get_template(operation).ok() {
-        let json = serde_json::to_value(bson_doc)?;
-        let ctx = context! { row => json };
+    if let Ok(tmpl) = env.get_template(&operation) {
         let rendered = tmpl.render(ctx)?;
         let result: Value = serde_json::from_str(&rendered)?;
-        Ok(Some(result))
+        Ok(Some((operation, result)))
     } else {
         Err(anyhow!("Missing template for operation '{}'.", operation))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(yaml: &str) -> FieldDef {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn converts(def: &FieldDef, value: &str) -> bool {
+        convert_value(value, def, "f", 1).is_ok()
+    }
+
+    #[test]
+    fn rows_are_routed_through_op_map() {
+        let mut env = crate::template::template_environment(Default::default());
+        env.add_template("insert", "{{ row | tojson }}").unwrap();
+        env.add_template("upsert", r#"{"filter": {"_id": {{ row._id }}}, "update": {"$set": {"n": {{ row.n }}}}}"#).unwrap();
+        env.add_template("delete", r#"{"filter": {"_id": {{ row._id }}}}"#).unwrap();
+        let op_map: HashMap<String, String> =
+            [("I", "insert"), ("U", "upsert"), ("D", "delete")].map(|(k, v)| (k.to_string(), v.to_string())).into();
+        let origin = RowOrigin { file: "cdc.csv", row_num: 1, run_id: "run" };
+        let row = bson::doc! { "_id": 7, "n": 1 };
+        let column = Operation::Column("op".to_string());
+        let route = |op_value: Option<&str>| render_operation(&env, &column, &op_map, &row, op_value, false, &origin);
+
+        let (operation, rendered) = route(Some("I")).unwrap().unwrap();
+        assert_eq!((operation.as_str(), rendered), ("insert", serde_json::json!({ "_id": 7, "n": 1 })));
+        let (operation, rendered) = route(Some(" U ")).unwrap().unwrap();
+        assert_eq!(operation, "upsert");
+        assert_eq!(rendered["update"], serde_json::json!({ "$set": { "n": 1 } }));
+        let (operation, rendered) = route(Some("D")).unwrap().unwrap();
+        assert_eq!((operation.as_str(), rendered), ("delete", serde_json::json!({ "filter": { "_id": 7 } })));
+        assert_eq!(route(Some("delete")).unwrap().unwrap().0, "delete");
+
+        assert!(route(Some("X")).unwrap_err().to_string().contains("Unknown operation 'X'"));
+        assert!(route(None).is_err());
+        assert!(route(Some("")).is_err());
+    }
+
+    #[test]
+    fn op_expr_results_are_routed_through_op_map() {
+        let mut env = crate::template::template_environment(Default::default());
+        env.add_template("delete", r#"{"filter": {"_id": {{ row._id }}}}"#).unwrap();
+        env.add_template(OP_EXPR_TEMPLATE, "{{ 'D' if row.gone else 'I' }}").unwrap();
+        let op_map = HashMap::from([("D".to_string(), "delete".to_string())]);
+        let origin = RowOrigin { file: "cdc.csv", row_num: 1, run_id: "run" };
+        let row = bson::doc! { "_id": 7, "gone": true };
+        let (operation, _) = render_operation(&env, &Operation::Expr, &op_map, &row, None, false, &origin).unwrap().unwrap();
+        assert_eq!(operation, "delete");
+    }
+
+    #[test]
+    fn long_bounds_are_compared_exactly() {
+        let def = field("type: long\nmin: -9007199254740993\nmax: 9007199254740992");
+        assert!(converts(&def, "9007199254740992"));
+        assert!(!converts(&def, "9007199254740993"));
+        assert!(converts(&def, "-9007199254740993"));
+        assert!(!converts(&def, "-9007199254740994"));
+    }
+
+    #[test]
+    fn decimal_bounds_are_compared_exactly() {
+        let def = field("type: decimal\nmin: \"-1.5E+3\"\nmax: \"0.30000000000000000001\"");
+        assert!(converts(&def, "0.30000000000000000001"));
+        assert!(!converts(&def, "0.30000000000000000002"));
+        assert!(converts(&def, "-1500.00"));
+        assert!(!converts(&def, "-1500.01"));
+        assert!(converts(&def, "0"));
+    }
+
+    #[test]
+    fn decimals_compare_by_value() {
+        assert_eq!(compare_decimal("1.50", "1.5"), Some(Ordering::Equal));
+        assert_eq!(compare_decimal("-0", "0.000"), Some(Ordering::Equal));
+        assert_eq!(compare_decimal("0.09", "1E-1"), Some(Ordering::Less));
+        assert_eq!(compare_decimal("-2", "-10"), Some(Ordering::Greater));
+        assert_eq!(compare_decimal("12e1", "+119.9"), Some(Ordering::Greater));
+        assert_eq!(compare_decimal("NaN", "1"), None);
+    }
+
+    #[test]
+    fn other_constraints_reject_values() {
+        let def = field("type: string\nenum: [a, b]");
+        assert!(converts(&def, "a"));
+        assert!(!converts(&def, "c"));
+
+        let def = field("type: string\npattern: \"^[A-Z]{2}[0-9]+$\"\nmin_length: 3\nmax_length: 4");
+        assert!(converts(&def, "AB12"));
+        assert!(!converts(&def, "ab12"));
+        assert!(!converts(&def, "AB123"));
+
+        let def = field("type: double\nmin: 0.5");
+        assert!(converts(&def, "0.5"));
+        assert!(!converts(&def, "0.49"));
+
+        let def = field("type: date\nmin: \"2024-01-01T00:00:00Z\"");
+        assert!(converts(&def, "2024-01-01T00:00:00Z"));
+        assert!(!converts(&def, "2023-12-31T23:59:59Z"));
+        assert!(def.date_bounds.get().is_some_and(|(min, max)| min.is_some() && max.is_none()));
+    }
+}
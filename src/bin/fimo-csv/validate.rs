@@ -0,0 +1,379 @@
+// src/validate.rs
+use crate::mapping::{scalar_text, DefaultValue, FieldDef, FieldMapping, OnEmpty, ELEMENT_TYPES, FIELD_TYPES};
+use crate::reject::strip_row_prefix;
+use crate::transform::{convert_scalar, convert_value};
+use std::collections::HashMap;
+use std::fmt;
+
+const RANGE_TYPES: [&str; 6] = ["int", "long", "double", "decimal", "date", "timestamp"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found in the mapping file, with the position of the offending
+/// YAML key when it can be located.
+#[derive(Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Option<(usize, usize)>,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.location {
+            Some((line, column)) => write!(f, "{} at line {} column {}: {}", label, line, column, self.message),
+            None => write!(f, "{}: {}", label, self.message),
+        }
+    }
+}
+
+/// Checks every field definition before any row is read: known types,
+/// options that fit the type, parseable target paths and usable defaults.
+pub fn validate_mapping(mapping: &FieldMapping, text: &str) -> Vec<Issue> {
+    let locator = YamlLocator::new(text);
+    let mut issues = Vec::new();
+    let mut targets: HashMap<String, &str> = HashMap::new();
+
+    let mut keys: Vec<&String> = mapping.0.keys().collect();
+    keys.sort_by_key(|key| locator.field(key));
+
+    for key in keys {
+        let def = &mapping.0[key];
+        let mut error = |option: Option<&str>, message: String| {
+            issues.push(Issue {
+                severity: Severity::Error,
+                message,
+                location: match option {
+                    Some(option) => locator.option(key, option).or_else(|| locator.field(key)),
+                    None => locator.field(key),
+                },
+            });
+        };
+
+        if !FIELD_TYPES.contains(&def.r#type.as_str()) {
+            error(
+                Some("type"),
+                format!("Unknown type '{}' for field '{}', expected one of: {}", def.r#type, key, FIELD_TYPES.join(", ")),
+            );
+            continue;
+        }
+
+        let is_array = def.r#type == "array";
+        let value_type = if is_array { def.element_type.as_deref().unwrap_or("string") } else { def.r#type.as_str() };
+
+        if is_array && !ELEMENT_TYPES.contains(&value_type) {
+            error(
+                Some("element_type"),
+                format!("Unknown element_type '{}' for field '{}', expected one of: {}", value_type, key, ELEMENT_TYPES.join(", ")),
+            );
+            continue;
+        }
+
+        if value_type != "bool" {
+            if def.truthy.is_some() {
+                error(Some("truthy"), format!("Option 'truthy' only applies to bool fields, but '{}' is {}", key, describe(def)));
+            }
+            if def.falsy.is_some() {
+                error(Some("falsy"), format!("Option 'falsy' only applies to bool fields, but '{}' is {}", key, describe(def)));
+            }
+        }
+        if value_type != "date" && def.formats.is_some() {
+            error(Some("formats"), format!("Option 'formats' only applies to date fields, but '{}' is {}", key, describe(def)));
+        }
+
+        if !is_array {
+            let array_options = [
+                ("separator", def.separator.is_some()),
+                ("element_type", def.element_type.is_some()),
+                ("trim", def.trim),
+                ("unique", def.unique),
+            ];
+            for (option, set) in array_options {
+                if set {
+                    error(Some(option), format!("Option '{}' only applies to array fields, but '{}' is {}", option, key, describe(def)));
+                }
+            }
+        } else if def.separator.as_deref() == Some("") {
+            error(Some("separator"), format!("Option 'separator' for field '{}' must not be empty", key));
+        }
+
+        match &def.default {
+            Some(DefaultValue::Generate { generate }) if !["now", "objectId", "uuid"].contains(&generate.as_str()) => {
+                error(
+                    Some("default"),
+                    format!("Unknown default generator '{}' for field '{}', expected one of: now, objectId, uuid", generate, key),
+                );
+            }
+            Some(DefaultValue::Generate { .. }) => {}
+            Some(DefaultValue::Literal(serde_yaml::Value::Null)) => {}
+            Some(DefaultValue::Literal(literal)) => match scalar_text(literal) {
+                None => error(Some("default"), format!("Default for field '{}' must be a scalar", key)),
+                Some(text) => {
+                    if let Err(e) = convert_value(&text, def, key, 0) {
+                        error(Some("default"), format!("Default '{}' is not valid for field '{}': {}", text, key, strip_row_prefix(&e.to_string())));
+                    }
+                }
+            },
+            None if def.on_empty() == OnEmpty::Default => {
+                error(Some("on_empty"), format!("Field '{}' uses on_empty: default but has no 'default'", key));
+            }
+            None => {}
+        }
+
+        if let Some(Err(e)) = def.pattern_regex() {
+            error(Some("pattern"), format!("{} for field '{}'", e, key));
+        }
+
+        if let Some(allowed) = &def.r#enum {
+            if allowed.iter().any(|a| scalar_text(a).is_none()) {
+                error(Some("enum"), format!("Option 'enum' for field '{}' must only list scalar values", key));
+            }
+        }
+
+        let has_bounds = def.min.is_some() || def.max.is_some();
+        if has_bounds && !RANGE_TYPES.contains(&value_type) {
+            error(
+                Some(if def.min.is_some() { "min" } else { "max" }),
+                format!("Options 'min'/'max' only apply to numeric and date fields, but '{}' is {}", key, describe(def)),
+            );
+        } else {
+            for (option, bound) in [("min", &def.min), ("max", &def.max)] {
+                let Some(bound) = bound else { continue };
+                let valid = match scalar_text(bound) {
+                    Some(text) if value_type == "date" => convert_scalar(&text, "date", def, key, 0).is_ok(),
+                    Some(text) => text.parse::<f64>().is_ok(),
+                    None => false,
+                };
+                if !valid {
+                    error(Some(option), format!("Option '{}' for field '{}' is not a valid {} bound", option, key, value_type));
+                }
+            }
+        }
+
+        let has_lengths = def.min_length.is_some() || def.max_length.is_some();
+        if has_lengths && value_type != "string" {
+            error(
+                Some(if def.min_length.is_some() { "min_length" } else { "max_length" }),
+                format!("Options 'min_length'/'max_length' only apply to string fields, but '{}' is {}", key, describe(def)),
+            );
+        }
+        if let (Some(min), Some(max)) = (def.min_length, def.max_length) {
+            if min > max {
+                error(Some("min_length"), format!("Option 'min_length' for field '{}' is greater than 'max_length'", key));
+            }
+        }
+
+        if let (Some(target), Some(path)) = (&def.target, def.target_path()) {
+            match path {
+                Err(e) => error(Some("target"), e.to_string()),
+                Ok(_) => {
+                    if let Some(other) = targets.insert(target.clone(), key) {
+                        error(Some("target"), format!("Fields '{}' and '{}' both map to '{}'", other, key, target));
+                    }
+                }
+            }
+        }
+    }
+
+    issues
+}
+
+/// Cross-checks the mapping against the input header. Unmapped columns and
+/// mapped fields absent from the header are warnings; a missing required
+/// field without a default, or a missing routing column, would fail every
+/// row and is an error.
+pub fn check_headers(mapping: &FieldMapping, headers: &[String], routing_column: Option<&str>, text: &str) -> Vec<Issue> {
+    let locator = YamlLocator::new(text);
+    let mut issues = Vec::new();
+
+    if let Some(column) = routing_column.filter(|column| !headers.iter().any(|h| h == column)) {
+        issues.push(Issue {
+            severity: Severity::Error,
+            message: format!("Routing column '{}' is not in the input header", column),
+            location: None,
+        });
+    }
+
+    for header in headers {
+        // The routing column is only read to pick the operation.
+        if !mapping.0.contains_key(header) && routing_column != Some(header.as_str()) {
+            issues.push(Issue {
+                severity: Severity::Warning,
+                message: format!("Column '{}' has no mapping and will be stored as a string", header),
+                location: None,
+            });
+        }
+    }
+
+    let mut missing: Vec<(&String, &FieldDef)> = mapping
+        .0
+        .iter()
+        .filter(|(key, def)| !headers.contains(key) && def.on_empty() != OnEmpty::Default)
+        .collect();
+    missing.sort_by_key(|(key, _)| locator.field(key));
+
+    for (key, def) in missing {
+        issues.push(Issue {
+            severity: if def.required { Severity::Error } else { Severity::Warning },
+            message: format!("Mapped field '{}' is not in the input header", key),
+            location: locator.field(key),
+        });
+    }
+
+    issues
+}
+
+fn describe(def: &FieldDef) -> String {
+    match (&*def.r#type, &def.element_type) {
+        ("array", Some(element)) => format!("an array of {}", element),
+        ("array", None) => "an array of string".to_string(),
+        (field_type, _) => format!("'{}'", field_type),
+    }
+}
+
+/// Finds the line and column of keys in block-style YAML. serde_yaml only
+/// reports positions for syntax errors, so semantic checks look keys up here.
+struct YamlLocator<'a> {
+    lines: Vec<&'a str>,
+}
+
+impl<'a> YamlLocator<'a> {
+    fn new(text: &'a str) -> Self {
+        YamlLocator { lines: text.lines().collect() }
+    }
+
+    fn field(&self, key: &str) -> Option<(usize, usize)> {
+        self.find(&["fields", key]).or_else(|| self.find(&[key]))
+    }
+
+    fn option(&self, key: &str, option: &str) -> Option<(usize, usize)> {
+        self.find(&["fields", key, option]).or_else(|| self.find(&[key, option]))
+    }
+
+    fn find(&self, path: &[&str]) -> Option<(usize, usize)> {
+        let mut start = 0;
+        let mut end = self.lines.len();
+        let mut found = None;
+
+        for key in path {
+            // Keys of this block sit at the indentation of its first entry.
+            let indent = (start..end).map(|i| self.lines[i]).find(|line| is_content(line)).map(indentation)?;
+            let index = (start..end).find(|i| {
+                let line = self.lines[*i];
+                indentation(line) == indent && is_key_line(line.trim_start(), key)
+            })?;
+
+            found = Some((index + 1, indent + 1));
+            start = index + 1;
+            end = (start..end)
+                .find(|i| is_content(self.lines[*i]) && indentation(self.lines[*i]) <= indent)
+                .unwrap_or(end);
+        }
+
+        found
+    }
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+fn is_content(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    !trimmed.is_empty() && !trimmed.starts_with('#')
+}
+
+fn is_key_line(line: &str, key: &str) -> bool {
+    [format!("{}:", key), format!("\"{}\":", key), format!("'{}':", key)]
+        .iter()
+        .any(|prefix| line.starts_with(prefix.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapping::load_mapping;
+
+    fn issues(yaml: &str) -> Vec<String> {
+        let mapping = load_mapping(yaml).unwrap();
+        validate_mapping(&mapping.fields, yaml).iter().map(Issue::to_string).collect()
+    }
+
+    fn header_issues(yaml: &str, headers: &[&str], routing_column: Option<&str>) -> Vec<String> {
+        let mapping = load_mapping(yaml).unwrap();
+        let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+        check_headers(&mapping.fields, &headers, routing_column, yaml).iter().map(Issue::to_string).collect()
+    }
+
+    #[test]
+    fn a_valid_mapping_has_no_issues() {
+        assert!(issues("id:\n  type: int\n  min: 1\nname:\n  type: string\n  pattern: \"^[a-z]+$\"\n").is_empty());
+    }
+
+    #[test]
+    fn unknown_types_point_at_their_line() {
+        let yaml = "fields:\n  id:\n    type: int\n  price:\n    type: money\n";
+        assert_eq!(
+            issues(yaml),
+            [format!("error at line 5 column 5: Unknown type 'money' for field 'price', expected one of: {}", FIELD_TYPES.join(", "))]
+        );
+    }
+
+    #[test]
+    fn options_must_fit_the_type() {
+        let yaml = "count:\n  type: int\n  truthy: [\"y\"]\n  separator: \";\"\n  max_length: 3\n";
+        assert_eq!(
+            issues(yaml),
+            [
+                "error at line 3 column 3: Option 'truthy' only applies to bool fields, but 'count' is 'int'",
+                "error at line 4 column 3: Option 'separator' only applies to array fields, but 'count' is 'int'",
+                "error at line 5 column 3: Options 'min_length'/'max_length' only apply to string fields, but 'count' is 'int'",
+            ]
+        );
+    }
+
+    #[test]
+    fn defaults_patterns_and_bounds_are_checked() {
+        let yaml = "a:\n  type: int\n  default: lots\nb:\n  type: string\n  pattern: \"(\"\nc:\n  type: double\n  max: high\nd:\n  type: date\n  on_empty: default\n";
+        let found = issues(yaml);
+        assert_eq!(found.len(), 4, "{:?}", found);
+        assert!(found[0].starts_with("error at line 3 column 3: Default 'lots' is not valid for field 'a'"));
+        assert!(found[1].starts_with("error at line 6 column 3: Invalid pattern '('"));
+        assert_eq!(found[2], "error at line 9 column 3: Option 'max' for field 'c' is not a valid double bound");
+        assert_eq!(found[3], "error at line 12 column 3: Field 'd' uses on_empty: default but has no 'default'");
+    }
+
+    #[test]
+    fn two_fields_cannot_share_a_target() {
+        let yaml = "city:\n  type: string\n  target: address.city\ntown:\n  type: string\n  target: address.city\n";
+        assert_eq!(
+            issues(yaml),
+            ["error at line 6 column 3: Fields 'city' and 'town' both map to 'address.city'"]
+        );
+    }
+
+    #[test]
+    fn missing_required_headers_are_errors_and_unmapped_columns_warnings() {
+        let yaml = "id:\n  type: int\n  required: true\nnote:\n  type: string\n";
+        assert_eq!(
+            header_issues(yaml, &["name", "op"], Some("op")),
+            [
+                "warning: Column 'name' has no mapping and will be stored as a string",
+                "error at line 1 column 1: Mapped field 'id' is not in the input header",
+                "warning at line 4 column 1: Mapped field 'note' is not in the input header",
+            ]
+        );
+        assert_eq!(
+            header_issues(yaml, &["id", "note"], Some("op")),
+            ["error: Routing column 'op' is not in the input header"]
+        );
+    }
+}
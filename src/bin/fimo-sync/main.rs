@@ -1,4 +1,6 @@
 mod cli;
+#[path = "../../common/mongo_version.rs"]
+mod mongo_version;
 mod sync;
 
 use crate::cli::Cli;
@@ -1,16 +1,13 @@
 // --- sync.rs ---
 use crate::cli::Cli;
+use crate::mongo_version::is_mongo_8_or_higher;
 use anyhow::{anyhow, Result};
 
 use mongodb::bson::Bson;
 use mongodb::change_stream::event::OperationType;
-use mongodb::change_stream::{
-    event::{ChangeStreamEvent, ResumeToken},
-    ChangeStream,
-};
+use mongodb::change_stream::event::{ChangeStreamEvent, ResumeToken};
 use mongodb::options::FullDocumentType;
 use mongodb::options::ReplaceOneModel;
-use mongodb::results::SummaryBulkWriteResult;
 use mongodb::Collection;
 use mongodb::{
     bson::{doc, Document},
@@ -18,12 +15,8 @@ use mongodb::{
     Client,
 };
 
-use futures::{
-    future::join_all,
-    stream::{FuturesUnordered, StreamExt},
-};
+use futures::stream::{FuturesUnordered, StreamExt};
 use serde::{Deserialize, Serialize};
-use serde_json;
 use std::fs;
 use std::path::Path;
 use std::sync::Arc;
@@ -244,10 +237,10 @@ pub async fn start_sync(args: Cli) -> Result<()> {
 
                 if let Some(doc) = &last_doc {
                     if field == "_id" {
-                        if let Some(id) = doc.get_object_id("_id").ok() {
+                        if let Ok(id) = doc.get_object_id("_id") {
                             resume_value = Some(Bson::ObjectId(id));
                             let serialized = serde_json::to_string(&ResumeCheckpoint {
-                                value: Bson::ObjectId(id.clone()),
+                                value: Bson::ObjectId(id),
                                 _id: Bson::ObjectId(id),
                             })?;
                             if let Some(path) = &args.resume_file {
@@ -259,10 +252,10 @@ pub async fn start_sync(args: Cli) -> Result<()> {
                             (doc.get(field), doc.get_object_id("_id").ok())
                         {
                             resume_value = Some(value.clone());
-                            last_id = Some(Bson::ObjectId(id.clone()));
+                            last_id = Some(Bson::ObjectId(id));
                             let checkpoint = ResumeCheckpoint {
                                 value: value.clone(),
-                                _id: Bson::ObjectId(id.clone()),
+                                _id: Bson::ObjectId(id),
                             };
                             if let Some(path) = &args.resume_file {
                                 let serialized = serde_json::to_string(&checkpoint)?;
@@ -294,24 +287,6 @@ fn process_change_event(change: &ChangeStreamEvent<Document>) -> Option<Document
     }
 }
 
-fn is_version_8_or_higher(version_str: &str) -> bool {
-    let parts: Vec<u32> = version_str
-        .split('.')
-        .filter_map(|x| x.parse::<u32>().ok())
-        .collect();
-    matches!(parts.as_slice(), [major, ..] if *major >= 8)
-}
-
-async fn is_mongo_8_or_higher(client: &Client) -> Result<bool> {
-    let admin_db = client.database("admin");
-    let result = admin_db.run_command(doc! { "buildInfo": 1 }).await?;
-    if let Some(Bson::String(version_str)) = result.get("version") {
-        Ok(is_version_8_or_higher(version_str))
-    } else {
-        Err(anyhow!("Could not determine MongoDB version"))
-    }
-}
-
 pub async fn write_to_target(
     client: &Client,
     collection: &Collection<Document>,
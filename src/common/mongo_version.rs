@@ -0,0 +1,37 @@
+// src/common/mongo_version.rs
+// Shared by the binaries with `#[path = "../../common/mongo_version.rs"] mod mongo_version;`.
+use anyhow::{anyhow, Result};
+use mongodb::bson::{doc, Bson};
+use mongodb::Client;
+
+fn is_version_8_or_higher(version_str: &str) -> bool {
+    let parts: Vec<u32> = version_str
+        .split('.')
+        .filter_map(|x| x.parse::<u32>().ok())
+        .collect();
+    matches!(parts.as_slice(), [major, ..] if *major >= 8)
+}
+
+/// Client-level `bulk_write` needs MongoDB 8.0 or newer.
+pub async fn is_mongo_8_or_higher(client: &Client) -> Result<bool> {
+    let admin_db = client.database("admin");
+    let result = admin_db.run_command(doc! { "buildInfo": 1 }).await?;
+    if let Some(Bson::String(version_str)) = result.get("version") {
+        Ok(is_version_8_or_higher(version_str))
+    } else {
+        Err(anyhow!("Could not determine MongoDB version"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn major_versions_from_8_support_bulk_write() {
+        assert!(is_version_8_or_higher("8.0.4"));
+        assert!(is_version_8_or_higher("10.1.0-rc0"));
+        assert!(!is_version_8_or_higher("7.0.12"));
+        assert!(!is_version_8_or_higher("unknown"));
+    }
+}